@@ -0,0 +1,124 @@
+use std::fmt;
+
+use prost_types::Any;
+
+use crate::NamedMessage;
+
+/// The type URL prefix used by [`NamedMessage::TYPE_URL`], unless configured otherwise
+pub const DEFAULT_TYPE_URL_PREFIX: &str = "type.googleapis.com";
+
+/// Helpers to pack messages into a `prost_types::Any`, and unpack them back out.
+///
+/// This is implemented for every message with a [`NamedMessage`] impl.
+pub trait AnyMessage: NamedMessage + prost::Message + Sized {
+    /// Packs the message into an `Any`, using [`NamedMessage::TYPE_URL`].
+    fn pack_any(&self) -> Any {
+        Any {
+            type_url: Self::TYPE_URL.to_owned(),
+            value: self.encode_to_vec(),
+        }
+    }
+
+    /// Packs the message into an `Any`, with a type URL made of `prefix` and the message name.
+    fn pack_any_with_prefix(&self, prefix: &str) -> Any {
+        Any {
+            type_url: format!("{}/{}", prefix.trim_end_matches('/'), Self::NAME),
+            value: self.encode_to_vec(),
+        }
+    }
+
+    /// Unpacks a message of this type from an `Any`.
+    fn unpack_any(any: &Any) -> Result<Self, UnpackError>
+    where
+        Self: Default,
+    {
+        if !Self::is(any) {
+            return Err(UnpackError::TypeMismatch {
+                expected: Self::NAME,
+                actual: any.type_url.clone(),
+            });
+        }
+        Self::decode(any.value.as_slice()).map_err(UnpackError::Decode)
+    }
+
+    /// Whether the `Any` holds a message of this type, whatever the prefix of its type URL.
+    fn is(any: &Any) -> bool {
+        let name = match any.type_url.rsplit_once('/') {
+            Some((_, name)) => name,
+            None => &any.type_url,
+        };
+        name == Self::NAME
+    }
+}
+
+impl<T: NamedMessage + prost::Message> AnyMessage for T {}
+
+/// The error returned when a message can't be unpacked from an `Any`.
+#[derive(Debug)]
+pub enum UnpackError {
+    /// The `Any` holds another type of message
+    TypeMismatch {
+        expected: &'static str,
+        actual: String,
+    },
+    /// The `Any` holds a type of message that is not known
+    UnknownType(String),
+    /// The message could not be decoded
+    Decode(prost::DecodeError),
+}
+
+impl fmt::Display for UnpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnpackError::TypeMismatch { expected, actual } => {
+                write!(f, "expected a {expected} message, found {actual}")
+            }
+            UnpackError::UnknownType(type_url) => write!(f, "unknown message type {type_url}"),
+            UnpackError::Decode(_) => write!(f, "failed to decode the message"),
+        }
+    }
+}
+
+impl std::error::Error for UnpackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UnpackError::TypeMismatch { .. } | UnpackError::UnknownType(_) => None,
+            UnpackError::Decode(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnyMessage, UnpackError};
+
+    #[test]
+    fn pack_and_unpack_any() {
+        let timestamp = prost_types::Timestamp {
+            seconds: 42,
+            nanos: 0,
+        };
+
+        let any = timestamp.pack_any();
+        assert_eq!(
+            any.type_url,
+            "type.googleapis.com/google.protobuf.Timestamp"
+        );
+        assert!(prost_types::Timestamp::is(&any));
+        assert!(!prost_types::Duration::is(&any));
+        assert_eq!(prost_types::Timestamp::unpack_any(&any).unwrap(), timestamp);
+
+        let any = timestamp.pack_any_with_prefix("example.com/types/");
+        assert_eq!(any.type_url, "example.com/types/google.protobuf.Timestamp");
+        assert_eq!(prost_types::Timestamp::unpack_any(&any).unwrap(), timestamp);
+
+        let err = prost_types::Duration::unpack_any(&any).unwrap_err();
+        assert!(matches!(
+            err,
+            UnpackError::TypeMismatch {
+                expected: "google.protobuf.Duration",
+                ..
+            }
+        ));
+    }
+}
@@ -1,6 +1,17 @@
+mod any;
+mod registry;
+mod service;
+
+pub use any::{AnyMessage, UnpackError, DEFAULT_TYPE_URL_PREFIX};
+pub use registry::{DynMessage, MessageRegistry, RegisteredMessage};
+pub use service::{MethodDescriptor, MethodKind, NamedService};
+
 /// A trait to provide a static reference to the message's name
 pub trait NamedMessage {
     const NAME: &'static str;
+    /// The type URL of the message when packed into a `prost_types::Any`, usually
+    /// [`DEFAULT_TYPE_URL_PREFIX`] followed by a `/` and [`NamedMessage::NAME`]
+    const TYPE_URL: &'static str;
 }
 
 // This derive is hidden since it doesn't do anything interesting.
@@ -10,139 +21,204 @@ pub trait NamedMessage {
 #[doc(hidden)]
 pub use grpc_build_derive::NamedMessage;
 
+/// A trait to provide a static reference to the enum's name, and to the name and number of each
+/// of its values
+pub trait NamedEnum {
+    const NAME: &'static str;
+    /// The name and number of each value, as in the proto definition
+    const VALUES: &'static [(&'static str, i32)];
+
+    /// Returns the name of the value with the given number, if there is one.
+    fn value_name(number: i32) -> Option<&'static str> {
+        Self::VALUES
+            .iter()
+            .find(|(_, value)| *value == number)
+            .map(|(name, _)| *name)
+    }
+}
+
+// Hidden for the same reason as the `NamedMessage` derive.
+#[doc(hidden)]
+pub use grpc_build_derive::NamedEnum;
+
 impl NamedMessage for bool {
     const NAME: &'static str = "google.protobuf.BoolValue";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.BoolValue";
 }
 impl NamedMessage for bytes::Bytes {
     const NAME: &'static str = "google.protobuf.BytesValue";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.BytesValue";
 }
 impl NamedMessage for Vec<u8> {
     const NAME: &'static str = "google.protobuf.BytesValue";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.BytesValue";
 }
 impl NamedMessage for f64 {
     const NAME: &'static str = "google.protobuf.DoubleValue";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.DoubleValue";
 }
 impl NamedMessage for () {
     const NAME: &'static str = "google.protobuf.Empty";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Empty";
 }
 impl NamedMessage for f32 {
     const NAME: &'static str = "google.protobuf.FloatValue";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.FloatValue";
 }
 impl NamedMessage for i32 {
     const NAME: &'static str = "google.protobuf.Int32Value";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Int32Value";
 }
 impl NamedMessage for i64 {
     const NAME: &'static str = "google.protobuf.Int64Value";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Int64Value";
 }
 impl NamedMessage for String {
     const NAME: &'static str = "google.protobuf.StringValue";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.StringValue";
 }
 impl NamedMessage for u32 {
     const NAME: &'static str = "google.protobuf.UInt32Value";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.UInt32Value";
 }
 impl NamedMessage for u64 {
     const NAME: &'static str = "google.protobuf.UInt64Value";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.UInt64Value";
 }
 
 impl NamedMessage for prost_types::Any {
     const NAME: &'static str = "google.protobuf.Any";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Any";
 }
 impl NamedMessage for prost_types::Api {
     const NAME: &'static str = "google.protobuf.Api";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Api";
 }
 impl NamedMessage for prost_types::DescriptorProto {
     const NAME: &'static str = "google.protobuf.DescriptorProto";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.DescriptorProto";
 }
 impl NamedMessage for prost_types::Duration {
     const NAME: &'static str = "google.protobuf.Duration";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Duration";
 }
 impl NamedMessage for prost_types::Enum {
     const NAME: &'static str = "google.protobuf.Enum";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Enum";
 }
 impl NamedMessage for prost_types::EnumDescriptorProto {
     const NAME: &'static str = "google.protobuf.EnumDescriptorProto";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.EnumDescriptorProto";
 }
 impl NamedMessage for prost_types::EnumOptions {
     const NAME: &'static str = "google.protobuf.EnumOptions";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.EnumOptions";
 }
 impl NamedMessage for prost_types::EnumValue {
     const NAME: &'static str = "google.protobuf.EnumValue";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.EnumValue";
 }
 impl NamedMessage for prost_types::EnumValueDescriptorProto {
     const NAME: &'static str = "google.protobuf.EnumValueDescriptorProto";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.EnumValueDescriptorProto";
 }
 impl NamedMessage for prost_types::EnumValueOptions {
     const NAME: &'static str = "google.protobuf.EnumValueOptions";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.EnumValueOptions";
 }
 impl NamedMessage for prost_types::ExtensionRangeOptions {
     const NAME: &'static str = "google.protobuf.ExtensionRangeOptions";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.ExtensionRangeOptions";
 }
 impl NamedMessage for prost_types::Field {
     const NAME: &'static str = "google.protobuf.Field";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Field";
 }
 impl NamedMessage for prost_types::FieldDescriptorProto {
     const NAME: &'static str = "google.protobuf.FieldDescriptorProto";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.FieldDescriptorProto";
 }
 impl NamedMessage for prost_types::FieldMask {
     const NAME: &'static str = "google.protobuf.FieldMask";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.FieldMask";
 }
 impl NamedMessage for prost_types::FieldOptions {
     const NAME: &'static str = "google.protobuf.FieldOptions";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.FieldOptions";
 }
 impl NamedMessage for prost_types::GeneratedCodeInfo {
     const NAME: &'static str = "google.protobuf.GeneratedCodeInfo";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.GeneratedCodeInfo";
 }
 impl NamedMessage for prost_types::ListValue {
     const NAME: &'static str = "google.protobuf.ListValue";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.ListValue";
 }
 impl NamedMessage for prost_types::MessageOptions {
     const NAME: &'static str = "google.protobuf.MessageOptions";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.MessageOptions";
 }
 impl NamedMessage for prost_types::Method {
     const NAME: &'static str = "google.protobuf.Method";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Method";
 }
 impl NamedMessage for prost_types::MethodDescriptorProto {
     const NAME: &'static str = "google.protobuf.MethodDescriptorProto";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.MethodDescriptorProto";
 }
 impl NamedMessage for prost_types::MethodOptions {
     const NAME: &'static str = "google.protobuf.MethodOptions";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.MethodOptions";
 }
 impl NamedMessage for prost_types::Mixin {
     const NAME: &'static str = "google.protobuf.Mixin";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Mixin";
 }
 impl NamedMessage for prost_types::OneofDescriptorProto {
     const NAME: &'static str = "google.protobuf.OneofDescriptorProto";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.OneofDescriptorProto";
 }
 impl NamedMessage for prost_types::OneofOptions {
     const NAME: &'static str = "google.protobuf.OneofOptions";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.OneofOptions";
 }
 impl NamedMessage for prost_types::Option {
     const NAME: &'static str = "google.protobuf.Option";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Option";
 }
 impl NamedMessage for prost_types::ServiceDescriptorProto {
     const NAME: &'static str = "google.protobuf.ServiceDescriptorProto";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.ServiceDescriptorProto";
 }
 impl NamedMessage for prost_types::ServiceOptions {
     const NAME: &'static str = "google.protobuf.ServiceOptions";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.ServiceOptions";
 }
 impl NamedMessage for prost_types::SourceCodeInfo {
     const NAME: &'static str = "google.protobuf.SourceCodeInfo";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.SourceCodeInfo";
 }
 impl NamedMessage for prost_types::SourceContext {
     const NAME: &'static str = "google.protobuf.SourceContext";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.SourceContext";
 }
 impl NamedMessage for prost_types::Struct {
     const NAME: &'static str = "google.protobuf.Struct";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Struct";
 }
 impl NamedMessage for prost_types::Timestamp {
     const NAME: &'static str = "google.protobuf.Timestamp";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Timestamp";
 }
 impl NamedMessage for prost_types::Type {
     const NAME: &'static str = "google.protobuf.Type";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Type";
 }
 impl NamedMessage for prost_types::UninterpretedOption {
     const NAME: &'static str = "google.protobuf.UninterpretedOption";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.UninterpretedOption";
 }
 impl NamedMessage for prost_types::Value {
     const NAME: &'static str = "google.protobuf.Value";
+    const TYPE_URL: &'static str = "type.googleapis.com/google.protobuf.Value";
 }
@@ -0,0 +1,143 @@
+use std::{any::Any, fmt};
+
+use prost::DecodeError;
+
+use crate::{NamedMessage, UnpackError};
+
+/// A decoded message, which can be downcast to its type
+pub type DynMessage = Box<dyn Any + Send + Sync>;
+
+/// A message type, along with the functions to handle its messages without knowing their type
+/// statically.
+#[derive(Clone, Copy)]
+pub struct RegisteredMessage {
+    /// The [`NamedMessage::NAME`] of the message
+    pub name: &'static str,
+    /// The [`NamedMessage::TYPE_URL`] of the message
+    pub type_url: &'static str,
+    /// Decodes a message of this type, which can be downcast back to it
+    pub decode: fn(&[u8]) -> Result<DynMessage, DecodeError>,
+    /// Encodes a message of this type, or returns `None` if it is of another type
+    pub encode: fn(&dyn Any) -> Option<Vec<u8>>,
+    /// Decodes a message of this type and formats it with its `Debug` impl
+    pub debug: fn(&[u8]) -> Result<String, DecodeError>,
+}
+
+impl RegisteredMessage {
+    /// The entry for the message type `T`.
+    pub const fn of<T>() -> Self
+    where
+        T: NamedMessage + prost::Message + Default + 'static,
+    {
+        Self {
+            name: T::NAME,
+            type_url: T::TYPE_URL,
+            decode: decode::<T>,
+            encode: encode::<T>,
+            debug: debug::<T>,
+        }
+    }
+}
+
+impl fmt::Debug for RegisteredMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegisteredMessage")
+            .field("name", &self.name)
+            .field("type_url", &self.type_url)
+            .finish_non_exhaustive()
+    }
+}
+
+fn decode<T>(buf: &[u8]) -> Result<DynMessage, DecodeError>
+where
+    T: prost::Message + Default + 'static,
+{
+    Ok(Box::new(T::decode(buf)?))
+}
+
+fn encode<T>(message: &dyn Any) -> Option<Vec<u8>>
+where
+    T: prost::Message + 'static,
+{
+    message.downcast_ref::<T>().map(T::encode_to_vec)
+}
+
+fn debug<T>(buf: &[u8]) -> Result<String, DecodeError>
+where
+    T: prost::Message + Default,
+{
+    Ok(format!("{:?}", T::decode(buf)?))
+}
+
+/// A set of message types that can be looked up by name, such as the registry generated by
+/// `grpc_build` next to the root `mod.rs`.
+pub trait MessageRegistry {
+    /// Every message type in the registry
+    fn messages(&self) -> &[RegisteredMessage];
+
+    /// Finds a message type by its full proto name, e.g. `my.package.Message`.
+    fn find(&self, name: &str) -> Option<&RegisteredMessage> {
+        self.messages().iter().find(|message| message.name == name)
+    }
+
+    /// Finds a message type by its type URL, whatever its prefix.
+    fn find_by_type_url(&self, type_url: &str) -> Option<&RegisteredMessage> {
+        let name = match type_url.rsplit_once('/') {
+            Some((_, name)) => name,
+            None => type_url,
+        };
+        self.find(name)
+    }
+
+    /// Decodes the message packed into an `Any`, which can be downcast to its type.
+    fn decode_any(&self, any: &prost_types::Any) -> Result<DynMessage, UnpackError> {
+        let message = self
+            .find_by_type_url(&any.type_url)
+            .ok_or_else(|| UnpackError::UnknownType(any.type_url.clone()))?;
+        (message.decode)(&any.value).map_err(UnpackError::Decode)
+    }
+}
+
+impl MessageRegistry for [RegisteredMessage] {
+    fn messages(&self) -> &[RegisteredMessage] {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MessageRegistry, RegisteredMessage};
+    use crate::{AnyMessage, UnpackError};
+
+    static REGISTRY: &[RegisteredMessage] = &[
+        RegisteredMessage::of::<prost_types::Duration>(),
+        RegisteredMessage::of::<prost_types::Timestamp>(),
+    ];
+
+    #[test]
+    fn decode_by_name() {
+        let timestamp = prost_types::Timestamp {
+            seconds: 42,
+            nanos: 0,
+        };
+
+        let message = REGISTRY.find("google.protobuf.Timestamp").unwrap();
+        let decoded = (message.decode)(&prost::Message::encode_to_vec(&timestamp)).unwrap();
+        assert_eq!(decoded.downcast_ref(), Some(&timestamp));
+        assert_eq!(
+            (message.encode)(decoded.as_ref()),
+            Some(prost::Message::encode_to_vec(&timestamp))
+        );
+        assert_eq!((message.encode)(&prost_types::Duration::default()), None);
+        assert_eq!(
+            (message.debug)(&prost::Message::encode_to_vec(&timestamp)).unwrap(),
+            format!("{timestamp:?}")
+        );
+
+        let decoded = REGISTRY.decode_any(&timestamp.pack_any()).unwrap();
+        assert_eq!(decoded.downcast_ref(), Some(&timestamp));
+
+        let err = REGISTRY.decode_any(&().pack_any()).unwrap_err();
+        assert!(matches!(err, UnpackError::UnknownType(_)));
+    }
+}
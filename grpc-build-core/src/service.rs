@@ -0,0 +1,55 @@
+/// A trait to provide static references to a gRPC service's name and methods
+pub trait NamedService {
+    /// The fully qualified name of the service, e.g. `my.package.Greeter`, which is also the
+    /// `NAME` of tonic's `NamedService` for the generated servers
+    const FULL_NAME: &'static str;
+    /// Every method of the service, in the order of the proto definition
+    const METHODS: &'static [MethodDescriptor];
+
+    /// Finds a method by its name in the proto definition, e.g. `SayHello`.
+    fn method(name: &str) -> Option<&'static MethodDescriptor> {
+        Self::METHODS.iter().find(|method| method.name == name)
+    }
+}
+
+/// Describes a method of a [`NamedService`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MethodDescriptor {
+    /// The name of the method in the proto definition, e.g. `SayHello`
+    pub name: &'static str,
+    /// The path of the method in gRPC requests, e.g. `/my.package.Greeter/SayHello`
+    pub path: &'static str,
+    /// The fully qualified name of the request message
+    pub input_type: &'static str,
+    /// The fully qualified name of the response message
+    pub output_type: &'static str,
+    /// Whether the requests and responses are streamed
+    pub kind: MethodKind,
+}
+
+/// Which sides of a method are streamed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MethodKind {
+    Unary,
+    ClientStreaming,
+    ServerStreaming,
+    BidiStreaming,
+}
+
+impl MethodKind {
+    /// Whether the client streams its requests.
+    pub const fn is_client_streaming(self) -> bool {
+        matches!(
+            self,
+            MethodKind::ClientStreaming | MethodKind::BidiStreaming
+        )
+    }
+
+    /// Whether the server streams its responses.
+    pub const fn is_server_streaming(self) -> bool {
+        matches!(
+            self,
+            MethodKind::ServerStreaming | MethodKind::BidiStreaming
+        )
+    }
+}
@@ -1,10 +1,11 @@
 //! proc macro to generate an associate function for all pb message types
 //! returning the full name of the message including the package namespace.
+//! Enums get the full name of the enum, and the name and number of each value.
 
 use proc_macro::TokenStream;
 use syn::{spanned::Spanned, DeriveInput, Lit, MetaNameValue};
 
-#[proc_macro_derive(NamedMessage, attributes(name))]
+#[proc_macro_derive(NamedMessage, attributes(name, type_url_prefix))]
 pub fn fully_qualified_name(input: TokenStream) -> TokenStream {
     let ast = syn::parse_macro_input!(input as DeriveInput);
 
@@ -22,26 +23,21 @@ fn impl_fully_qualified_name(ast: &syn::DeriveInput) -> syn::Result<TokenStream>
         syn::Data::Union(_) => return Ok(Default::default()),
     };
 
-    // search for #[name]
-    let mut name_attrs = ast.attrs.iter().filter(|attr| attr.path.is_ident("name"));
-
-    // Let's assume we only have one annotation
-    let meta = match name_attrs.next() {
-        Some(attr) => attr.parse_meta()?,
+    // prost applies the attributes of a message to the messages nested in it as well, so the name
+    // of the message itself is the longest one
+    let message_name = match find_longest_str_attr(&ast.attrs, "name")? {
+        Some(name) => name,
         None => return Err(syn::Error::new(ast.span(), "missing #[name] attribute")),
     };
 
-    // #[name = "pbname"] should map to a NameValue
-    //   path    Lit
-    let message_name = match meta {
-        syn::Meta::NameValue(MetaNameValue {
-            lit: Lit::Str(name),
-            ..
-        }) => name,
-        syn::Meta::NameValue(MetaNameValue { lit, .. }) => {
-            return Err(syn::Error::new(lit.span(), "message name MUST be a string"))
-        }
-        meta => return Err(syn::Error::new(meta.span(), "missing #[name] attribute")),
+    // #[type_url_prefix = "example.com"] overrides the default prefix of the type URL
+    let type_url = match find_str_attr(&ast.attrs, "type_url_prefix")? {
+        Some(prefix) => format!(
+            "{}/{}",
+            prefix.value().trim_end_matches('/'),
+            message_name.value()
+        ),
+        None => format!("type.googleapis.com/{}", message_name.value()),
     };
 
     let name = &ast.ident;
@@ -49,7 +45,110 @@ fn impl_fully_qualified_name(ast: &syn::DeriveInput) -> syn::Result<TokenStream>
     Ok(quote::quote! {
         impl ::grpc_build_core::NamedMessage for #name {
             const NAME: &'static ::core::primitive::str = #message_name;
+            const TYPE_URL: &'static ::core::primitive::str = #type_url;
+        }
+    }
+    .into())
+}
+
+#[proc_macro_derive(NamedEnum, attributes(name))]
+pub fn fully_qualified_enum_name(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_macro_input!(input as DeriveInput);
+
+    match impl_fully_qualified_enum_name(&ast) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn impl_fully_qualified_enum_name(ast: &syn::DeriveInput) -> syn::Result<TokenStream> {
+    // We only annotate enums, leaving out the ones of oneofs, whose variants hold the fields
+    let data = match &ast.data {
+        syn::Data::Enum(data) => data,
+        syn::Data::Struct(_) => return Ok(Default::default()),
+        syn::Data::Union(_) => return Ok(Default::default()),
+    };
+    if data
+        .variants
+        .iter()
+        .any(|variant| !matches!(variant.fields, syn::Fields::Unit))
+    {
+        return Ok(Default::default());
+    }
+
+    // As for messages, the name of the enum itself is the longest one
+    let enum_name = match find_longest_str_attr(&ast.attrs, "name")? {
+        Some(name) => name,
+        None => return Err(syn::Error::new(ast.span(), "missing #[name] attribute")),
+    };
+
+    // Each variant can be annotated with the name of the value in the proto definition,
+    // otherwise the name of the variant is used
+    let values = data
+        .variants
+        .iter()
+        .map(|variant| {
+            let ident = &variant.ident;
+            let value_name = match find_str_attr(&variant.attrs, "name")? {
+                Some(name) => name.value(),
+                None => ident.to_string(),
+            };
+            Ok(quote::quote! { (#value_name, Self::#ident as ::core::primitive::i32) })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let name = &ast.ident;
+
+    Ok(quote::quote! {
+        impl ::grpc_build_core::NamedEnum for #name {
+            const NAME: &'static ::core::primitive::str = #enum_name;
+            const VALUES: &'static [(&'static ::core::primitive::str, ::core::primitive::i32)] =
+                &[#(#values),*];
         }
     }
     .into())
 }
+
+/// Finds a string attribute such as `#[name = "pbname"]`, assuming there is only one.
+fn find_str_attr(attrs: &[syn::Attribute], ident: &str) -> syn::Result<Option<syn::LitStr>> {
+    // search for #[ident]
+    let mut name_attrs = attrs.iter().filter(|attr| attr.path.is_ident(ident));
+
+    // Let's assume we only have one annotation
+    match name_attrs.next() {
+        Some(attr) => parse_str_attr(attr, ident).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Finds the longest of the string attributes such as `#[name = "pbname"]`.
+fn find_longest_str_attr(
+    attrs: &[syn::Attribute],
+    ident: &str,
+) -> syn::Result<Option<syn::LitStr>> {
+    let values = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident(ident))
+        .map(|attr| parse_str_attr(attr, ident))
+        .collect::<syn::Result<Vec<_>>>()?;
+    Ok(values.into_iter().max_by_key(|value| value.value().len()))
+}
+
+fn parse_str_attr(attr: &syn::Attribute, ident: &str) -> syn::Result<syn::LitStr> {
+    // #[name = "pbname"] should map to a NameValue
+    //   path    Lit
+    match attr.parse_meta()? {
+        syn::Meta::NameValue(MetaNameValue {
+            lit: Lit::Str(name),
+            ..
+        }) => Ok(name),
+        syn::Meta::NameValue(MetaNameValue { lit, .. }) => Err(syn::Error::new(
+            lit.span(),
+            format!("{ident} MUST be a string"),
+        )),
+        meta => Err(syn::Error::new(
+            meta.span(),
+            format!("missing #[{ident}] attribute"),
+        )),
+    }
+}
@@ -1,85 +1,317 @@
 use std::{
+    collections::BTreeSet,
     ffi::OsString,
+    fmt::Display,
     path::{Path, PathBuf},
-    process::Command,
 };
 
-use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use walkdir::WalkDir;
 
+use crate::{
+    merge::Origins,
+    tree::{Layout, LayoutOptions, Tree},
+    Error,
+};
+
 // the use of these `inner` functions is a compile time optimisation. In this case it's probably
 // minimal but it improves how the code compiles. The inner functions are not generic, so can be built exactly once
 // but the outer functions are generic and must be built for every input type (String, &String, &str, &Path, etc).
 // Since the outer function just calls the inner function, this is very cheap, but still provides the ergonomic generic API
 
-pub fn prepare_out_dir(out_dir: impl AsRef<Path>) -> Result<()> {
-    fn inner(out_dir: &Path) -> Result<()> {
+pub fn prepare_out_dir(out_dir: impl AsRef<Path>) -> Result<(), Error> {
+    fn inner(out_dir: &Path) -> Result<(), Error> {
         if out_dir.exists() {
-            fs_err::remove_dir_all(out_dir).with_context(|| {
-                format!(
-                    "could not remove the output directory: {}",
-                    out_dir.display()
-                )
-            })?;
+            fs_err::remove_dir_all(out_dir)
+                .map_err(Error::io("remove the output directory", out_dir))?;
         }
 
-        fs_err::create_dir_all(out_dir).with_context(|| {
-            format!(
-                "could not create the output directory: {}",
-                out_dir.display()
-            )
-        })?;
+        fs_err::create_dir_all(out_dir)
+            .map_err(Error::io("create the output directory", out_dir))?;
 
         Ok(())
     }
     inner(out_dir.as_ref())
 }
 
+/// Moves the fully generated `staging` directory to `out_dir`, replacing the previous output.
+///
+/// Both must be on the same filesystem. The previous output is kept aside until the new one is in
+/// place, and restored if that fails. If it can't be restored either, it is left where it was kept
+/// aside, which the error points at.
+pub fn replace_out_dir(staging: impl AsRef<Path>, out_dir: impl AsRef<Path>) -> Result<(), Error> {
+    fn inner(staging: &Path, out_dir: &Path) -> Result<(), Error> {
+        if !out_dir.exists() {
+            fs_err::rename(staging, out_dir).map_err(Error::io("move the output to", out_dir))?;
+            return Ok(());
+        }
+
+        let parent = parent_dir(out_dir);
+        let backup_dir = tempfile::Builder::new()
+            .prefix(".grpc-build-previous")
+            .tempdir_in(parent)
+            .map_err(Error::io(
+                "create a directory for the previous output in",
+                parent,
+            ))?;
+        let backup = backup_dir.path().join("out");
+
+        fs_err::rename(out_dir, &backup)
+            .map_err(Error::io("move away the previous output", out_dir))?;
+
+        if let Err(err) = fs_err::rename(staging, out_dir) {
+            if let Err(restore_err) = fs_err::rename(&backup, out_dir) {
+                // Kept for the user to restore, as it is the only copy of the previous output
+                let backup = backup_dir.keep().join("out");
+                return Err(Error::io("restore the previous output from", backup)(
+                    restore_err,
+                ));
+            }
+            return Err(Error::io("move the output to", out_dir)(err));
+        }
+
+        Ok(())
+    }
+    inner(staging.as_ref(), out_dir.as_ref())
+}
+
+/// The directory containing `path`, which is the current directory for relative paths
+/// with a single component.
+pub(crate) fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
 /// Get all the `.proto` files within the provided directory
 pub fn get_protos(input: impl AsRef<Path>, follow_links: bool) -> impl Iterator<Item = PathBuf> {
-    fn inner(input: &Path, follow_links: bool) -> impl Iterator<Item = PathBuf> {
-        // TODO: maybe add this?
-        // println!("cargo:rerun-if-changed={}", input.display());
-
-        WalkDir::new(input)
-            .follow_links(follow_links)
-            .into_iter()
-            .filter_map(|r| r.map_err(|err| println!("cargo:warning={:?}", err)).ok())
-            .filter(|e| e.file_type().is_file())
-            .filter(|e| e.path().extension().map_or(false, |e| e == "proto"))
-            .map(|e| e.path().to_path_buf())
+    walk_protos(input.as_ref(), follow_links)
+        .filter_map(|r| r.map_err(|err| println!("cargo:warning={:?}", err)).ok())
+}
+
+/// Like [`get_protos`], along with the errors reading the directory.
+pub(crate) fn walk_protos(
+    input: &Path,
+    follow_links: bool,
+) -> impl Iterator<Item = Result<PathBuf, Error>> {
+    let dir = input.to_owned();
+    WalkDir::new(input)
+        .follow_links(follow_links)
+        .into_iter()
+        .filter(|r| match r {
+            Ok(e) => e.file_type().is_file() && e.path().extension().is_some_and(|e| e == "proto"),
+            Err(_) => true,
+        })
+        .map(move |r| r.map(|e| e.into_path()).map_err(Error::walk(&dir)))
+}
+
+/// Glob patterns selecting which of the discovered `.proto` files are compiled.
+///
+/// Patterns are matched against the path of the file relative to its input directory.
+#[derive(Debug, Default)]
+pub struct ProtoFilter {
+    include_only: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl ProtoFilter {
+    /// Creates a filter that keeps the files matching any of the `include_only` patterns (or all
+    /// of them if there are none), minus the ones matching any of the `exclude` patterns.
+    pub fn new<I, E>(include_only: I, exclude: E) -> Result<Self, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        E: IntoIterator,
+        E::Item: AsRef<str>,
+    {
+        fn build_set(
+            patterns: impl Iterator<Item = impl AsRef<str>>,
+        ) -> Result<Option<GlobSet>, Error> {
+            let mut builder = GlobSetBuilder::new();
+            let mut all_patterns = Vec::new();
+            for pattern in patterns {
+                let pattern = pattern.as_ref();
+                builder.add(
+                    GlobBuilder::new(pattern)
+                        .literal_separator(true)
+                        .build()
+                        .map_err(|source| Error::InvalidGlob {
+                            pattern: pattern.to_owned(),
+                            source,
+                        })?,
+                );
+                all_patterns.push(pattern.to_owned());
+            }
+
+            if all_patterns.is_empty() {
+                return Ok(None);
+            }
+            builder
+                .build()
+                .map(Some)
+                .map_err(|source| Error::InvalidGlob {
+                    pattern: all_patterns.join(", "),
+                    source,
+                })
+        }
+
+        Ok(Self {
+            include_only: build_set(include_only.into_iter())?,
+            exclude: build_set(exclude.into_iter())?.unwrap_or_default(),
+        })
+    }
+
+    /// Whether the file at `path`, relative to its input directory, should be compiled
+    pub fn is_match(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        self.include_only
+            .as_ref()
+            .map_or(true, |include_only| include_only.is_match(path))
+            && !self.exclude.is_match(path)
+    }
+}
+
+/// The files that differ between two directories, relative to them.
+#[derive(Debug, Default, PartialEq)]
+pub struct DirDiff {
+    /// Files only found in the expected directory
+    pub added: Vec<PathBuf>,
+    /// Files only found in the actual directory
+    pub removed: Vec<PathBuf>,
+    /// Files found in both, with different contents
+    pub changed: Vec<PathBuf>,
+}
+
+impl DirDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl Display for DirDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (kind, paths) in [
+            ("added", &self.added),
+            ("removed", &self.removed),
+            ("changed", &self.changed),
+        ] {
+            for path in paths {
+                writeln!(f, "  {}: {}", kind, path.display())?;
+            }
+        }
+        Ok(())
     }
-    inner(input.as_ref(), follow_links)
+}
+
+/// Compares the files in the `expected` directory against the `actual` one, which may not exist.
+/// The build manifest is ignored.
+pub fn compare_dirs(
+    expected: impl AsRef<Path>,
+    actual: impl AsRef<Path>,
+) -> Result<DirDiff, Error> {
+    fn files(dir: &Path) -> Result<BTreeSet<PathBuf>, Error> {
+        if !dir.exists() {
+            return Ok(BTreeSet::new());
+        }
+
+        let mut files = BTreeSet::new();
+        for entry in WalkDir::new(dir) {
+            let entry = entry.map_err(Error::walk(dir))?;
+            if entry.file_type().is_file() && entry.file_name() != crate::manifest::MANIFEST_FILE {
+                files.extend(entry.path().strip_prefix(dir).map(Path::to_path_buf));
+            }
+        }
+        Ok(files)
+    }
+
+    fn inner(expected: &Path, actual: &Path) -> Result<DirDiff, Error> {
+        let expected_files = files(expected)?;
+        let actual_files = files(actual)?;
+
+        let mut diff = DirDiff {
+            added: expected_files.difference(&actual_files).cloned().collect(),
+            removed: actual_files.difference(&expected_files).cloned().collect(),
+            changed: Vec::new(),
+        };
+
+        for path in expected_files.intersection(&actual_files) {
+            let read = |dir: &Path| {
+                let path = dir.join(path);
+                fs_err::read(&path).map_err(Error::io("read", &path))
+            };
+            if read(expected)? != read(actual)? {
+                diff.changed.push(path.clone());
+            }
+        }
+
+        Ok(diff)
+    }
+    inner(expected.as_ref(), actual.as_ref())
 }
 
 /// [`tonic_build::Builder::compile`] outputs all the rust files into the output dir all at the top level.
 /// This might not be the most desirable. Running this function converts the file into a more expected directory
 /// structure and generates the expected mod file output
-pub fn refactor(output: impl AsRef<Path>) -> Result<()> {
-    fn inner(output: &Path) -> Result<()> {
-        let tree: crate::tree::Tree = fs_err::read_dir(output)?
-            .filter_map(|r| r.map_err(|err| println!("cargo:warning={:?}", err)).ok())
-            .filter(|e| e.path().extension().map_or(false, |e| e == "rs"))
-            .filter(|e| !e.path().ends_with("mod.rs"))
-            .map(|e| e.path())
-            .collect();
-
-        tree.move_paths(output, OsString::new(), PathBuf::new())?;
-        fs_err::write(output.join("mod.rs"), tree.generate_module())?;
-
-        Command::new("rustfmt")
-            .arg(output.join("mod.rs"))
-            .spawn()
-            .context("failed to format the mod.rs output")?;
-
-        Ok(())
+pub fn refactor(output: impl AsRef<Path>) -> Result<(), Error> {
+    fn inner(output: &Path) -> Result<(), Error> {
+        refactor_tree(output, &LayoutOptions::default(), &Default::default())?;
+        crate::format::format_dir(output, &Default::default())
     }
     inner(output.as_ref())
 }
 
+/// Like [`refactor`], without formatting the files, and laying them out as `options` say.
+pub(crate) fn refactor_tree(
+    output: &Path,
+    options: &LayoutOptions,
+    origins: &Origins,
+) -> Result<(), Error> {
+    let root_file = output.join(&options.root_file_name);
+    crate::merge::merge_nested_modules(output, &root_file, origins)?;
+
+    let tree: Tree = fs_err::read_dir(output)
+        .map_err(Error::io("read", output))?
+        .map(|r| r.map(|e| e.path()).map_err(Error::io("read", output)))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|e| e == "rs"))
+        .filter(|path| *path != root_file)
+        .collect();
+
+    let module = match options.layout {
+        Layout::Directory => {
+            tree.move_paths_in_style(
+                output,
+                OsString::new(),
+                PathBuf::new(),
+                options.module_style,
+            )?;
+            tree.generate_module()
+        }
+        Layout::SingleFile => tree.inline_modules(output)?,
+    };
+    fs_err::write(&root_file, module).map_err(Error::io("write", &root_file))
+}
+
 #[cfg(test)]
 mod test {
-    use super::refactor;
+    use super::{refactor, refactor_tree, ProtoFilter};
+    use crate::tree::{LayoutOptions, ModuleStyle};
+
+    #[test]
+    fn proto_filter_includes_and_excludes() {
+        let filter = ProtoFilter::new(["api/**"], ["**/deprecated/*.proto"]).unwrap();
+
+        assert!(filter.is_match("api/v1/service.proto"));
+        assert!(!filter.is_match("api/deprecated/service.proto"));
+        assert!(!filter.is_match("fixtures/service.proto"));
+
+        let filter = ProtoFilter::new(None::<&str>, ["*.proto"]).unwrap();
+        assert!(!filter.is_match("service.proto"));
+        // `*` does not cross directories
+        assert!(filter.is_match("api/service.proto"));
+    }
 
     #[test]
     fn refactor_test_moves_files_to_correct_place() {
@@ -142,4 +374,30 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn refactor_in_mod_rs_style() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        for file in ["root.pak.a1.rs", "root.pak.rs", "root.rs", "other.rs"] {
+            std::fs::write(temp_dir.path().join(file), format!("// {} contents", file)).unwrap();
+        }
+
+        let options = LayoutOptions {
+            module_style: ModuleStyle::ModRs,
+            root_file_name: "lib.rs".to_owned(),
+            ..LayoutOptions::default()
+        };
+        refactor_tree(temp_dir.path(), &options, &Default::default()).unwrap();
+
+        let read = |file| std::fs::read_to_string(temp_dir.path().join(file)).unwrap();
+        assert_eq!(
+            read("root/pak/mod.rs"),
+            "// Module generated with `grpc_build`\npub mod a1;\n\n// root.pak.rs contents"
+        );
+        assert_eq!(read("root/pak/a1.rs"), "// root.pak.a1.rs contents");
+        assert!(read("root/mod.rs").contains("pub mod pak;"));
+        assert!(read("lib.rs").contains("pub mod other;\npub mod root;"));
+        assert!(!temp_dir.path().join("root.rs").exists());
+        assert!(!temp_dir.path().join("mod.rs").exists());
+    }
 }
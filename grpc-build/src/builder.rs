@@ -3,16 +3,46 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use crate::Error;
+
 /// A mirror of [`tonic_build::Builder`] for our own control
+#[derive(Debug)]
 pub struct Builder {
     pub(crate) tonic: tonic_build::Builder,
     pub(crate) prost: prost_build::Config,
     pub(crate) protoc_args: Vec<OsString>,
+    pub(crate) in_dirs: Vec<PathBuf>,
     pub(crate) out_dir: Option<PathBuf>,
     pub(crate) force: bool,
     pub(crate) default_module_name: Option<String>,
     pub(crate) follow_links: bool,
     pub(crate) file_descriptor_set_path: Option<PathBuf>,
+    pub(crate) include_paths: Vec<PathBuf>,
+    pub(crate) include_only: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+    pub(crate) emit_rerun_if_changed: bool,
+    pub(crate) emit_cargo_warnings: bool,
+    pub(crate) layout: crate::tree::LayoutOptions,
+    pub(crate) format: bool,
+    pub(crate) rustfmt: crate::format::Rustfmt,
+    pub(crate) incremental: bool,
+    pub(crate) type_url_prefix: Option<String>,
+    pub(crate) message_registry: bool,
+    pub(crate) embed_file_descriptor_set: bool,
+    pub(crate) embed_package_file_descriptor_sets: bool,
+    // Mirrors of the `tonic_build::Builder` and `prost_build::Config` options, which can't be
+    // read back from them, for the fingerprint of the incremental builds
+    pub(crate) build_client: bool,
+    pub(crate) build_server: bool,
+    pub(crate) compile_well_known_types: bool,
+    pub(crate) include_file: Option<PathBuf>,
+    pub(crate) extern_paths: Vec<(String, String)>,
+    /// The attributes added to the generated code, along with the method adding them and the
+    /// path they match
+    pub(crate) attributes: Vec<(&'static str, String, String)>,
+    /// The file the options were read from, if any, so that cargo reruns the build when it
+    /// changes
+    pub(crate) config_file: Option<PathBuf>,
 }
 
 impl Default for Builder {
@@ -21,20 +51,42 @@ impl Default for Builder {
             tonic: tonic_build::configure(),
             prost: Default::default(),
             protoc_args: Default::default(),
+            in_dirs: Vec::new(),
             out_dir: None,
             force: false,
             default_module_name: None,
             follow_links: false,
             file_descriptor_set_path: None,
+            include_paths: Vec::new(),
+            include_only: Vec::new(),
+            exclude: Vec::new(),
+            // `TARGET` is only set by cargo when running build scripts
+            emit_rerun_if_changed: std::env::var_os("TARGET").is_some(),
+            emit_cargo_warnings: std::env::var_os("TARGET").is_some(),
+            layout: Default::default(),
+            format: true,
+            rustfmt: Default::default(),
+            incremental: false,
+            type_url_prefix: None,
+            message_registry: false,
+            embed_file_descriptor_set: false,
+            embed_package_file_descriptor_sets: false,
+            build_client: true,
+            build_server: true,
+            compile_well_known_types: false,
+            include_file: None,
+            extern_paths: Vec::new(),
+            attributes: Vec::new(),
+            config_file: None,
         }
     }
 }
 
 impl Builder {
-    pub(crate) fn get_out_dir(&self) -> Result<PathBuf, anyhow::Error> {
+    pub(crate) fn get_out_dir(&self) -> Result<PathBuf, Error> {
         self.out_dir.clone().map(Ok).unwrap_or_else(|| {
             std::env::var_os("OUT_DIR")
-                .ok_or_else(|| anyhow::anyhow!("could not determine $OUT_DIR"))
+                .ok_or(Error::NoOutDir)
                 .map(Into::into)
         })
     }
@@ -43,11 +95,44 @@ impl Builder {
         Default::default()
     }
 
+    /// Creates a builder with the options of a `grpc-build.toml` config file. Every key is named
+    /// after the method it configures, e.g. `in_dirs`, `out_dir` or `type_attributes`, and the
+    /// relative paths are resolved from the directory of the file.
+    ///
+    /// The options can still be changed afterwards, and the input directories of the file are
+    /// built with [`Builder::build_configured`].
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::new().apply_config_file(path)
+    }
+
+    /// Sets the options of a `grpc-build.toml` config file, like [`Builder::from_config_file`],
+    /// over the options already set. The options the file doesn't have are left as they are.
+    pub fn apply_config_file(self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut builder = crate::config::Config::from_file(path)?.apply(self);
+        builder.config_file = Some(path.to_owned());
+        Ok(builder)
+    }
+
     pub fn force(mut self, force: bool) -> Self {
         self.force = force;
         self
     }
 
+    /// Skip the build entirely when neither the protos, the builder options nor the tools
+    /// changed since the last build into the same output directory. This is tracked in a
+    /// `.grpc-build-manifest` file written to the output directory, so it's best suited to
+    /// output directories that aren't checked in, like `OUT_DIR`.
+    ///
+    /// A build that is skipped succeeds even if the output directory exists and
+    /// [`Builder::force`] isn't set.
+    ///
+    /// This defaults to `false`.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
     /// Follow symbolic links when finding .proto files.
     ///
     /// This defaults to `false`.
@@ -56,26 +141,173 @@ impl Builder {
         self
     }
 
+    /// Print `cargo:rerun-if-changed` directives for the input and include directories, every
+    /// compiled proto and its imports, along with `cargo:rerun-if-env-changed` for the
+    /// `PROTOC` and `PROTOC_INCLUDE` environment variables.
+    ///
+    /// This defaults to `true` when running inside a cargo build script.
+    pub fn emit_rerun_if_changed(mut self, enable: bool) -> Self {
+        self.emit_rerun_if_changed = enable;
+        self
+    }
+
+    /// Print the errors reported for invalid protos as `cargo:warning` lines, so that they're
+    /// shown in the cargo output rather than only in the build script's log.
+    ///
+    /// This defaults to `true` when running inside a cargo build script.
+    pub fn emit_cargo_warnings(mut self, enable: bool) -> Self {
+        self.emit_cargo_warnings = enable;
+        self
+    }
+
+    /// Configures how the generated modules are laid out in the output directory.
+    ///
+    /// This defaults to [`Layout::Directory`](crate::Layout::Directory).
+    pub fn layout(mut self, layout: crate::Layout) -> Self {
+        self.layout.layout = layout;
+        self
+    }
+
+    /// Configures whether the modules with submodules are written to `pkg.rs` or `pkg/mod.rs`,
+    /// in the [`Layout::Directory`](crate::Layout::Directory) layout.
+    ///
+    /// This defaults to [`ModuleStyle::NonModRs`](crate::ModuleStyle::NonModRs).
+    pub fn module_style(mut self, style: crate::ModuleStyle) -> Self {
+        self.layout.module_style = style;
+        self
+    }
+
+    /// Configures the name of the root file, which declares the top-level modules, e.g. `lib.rs`.
+    ///
+    /// This defaults to `mod.rs`.
+    pub fn root_file_name(mut self, name: impl AsRef<str>) -> Self {
+        self.layout.root_file_name = name.as_ref().to_owned();
+        self
+    }
+
+    /// Format every generated file, with `rustfmt` if it is installed and with `prettyplease`
+    /// otherwise.
+    ///
+    /// This defaults to `true`.
+    pub fn format(mut self, enable: bool) -> Self {
+        self.format = enable;
+        self
+    }
+
+    /// Configures the `rustfmt` binary used to format the generated files. Unlike with the
+    /// default of `$RUSTFMT` or `rustfmt`, the build fails if it can't be run.
+    pub fn rustfmt_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rustfmt.path = Some(path.into());
+        self
+    }
+
+    /// Configures the edition `rustfmt` formats the generated files with.
+    ///
+    /// This defaults to `2021`.
+    pub fn rustfmt_edition(mut self, edition: impl AsRef<str>) -> Self {
+        self.rustfmt.edition = edition.as_ref().to_owned();
+        self
+    }
+
+    /// Configures the `rustfmt.toml` to format the generated files with.
+    pub fn rustfmt_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rustfmt.config_path = Some(path.into());
+        self
+    }
+
+    /// Add a directory to find the .proto files to compile in. These are compiled along with the
+    /// directories passed to [`Builder::build_all`] or [`Builder::check_all`].
+    pub fn in_dir(mut self, path: impl AsRef<Path>) -> Self {
+        self.in_dirs.push(path.as_ref().to_owned());
+        self
+    }
+
     pub fn out_dir(mut self, out_dir: impl AsRef<Path>) -> Self {
         self.out_dir = Some(out_dir.as_ref().to_owned());
         self
     }
 
+    /// Add a directory to resolve proto imports from.
+    ///
+    /// Protos that are only found under an include path are not generated themselves, unless
+    /// they are also part of an input directory. Use [`Builder::extern_path`] to point the
+    /// generated code at their Rust definitions.
+    pub fn include_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.include_paths.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Only compile the discovered .proto files matching this glob pattern. Can be called
+    /// multiple times, in which case a file matching any of the patterns is compiled.
+    ///
+    /// Patterns are matched against the path relative to the input directory, e.g. `api/**`.
+    pub fn include_only(mut self, glob: impl AsRef<str>) -> Self {
+        self.include_only.push(glob.as_ref().to_string());
+        self
+    }
+
+    /// Skip the discovered .proto files matching this glob pattern.
+    ///
+    /// Excluded files can still be imported by the compiled ones, but they are not generated.
+    pub fn exclude(mut self, glob: impl AsRef<str>) -> Self {
+        self.exclude.push(glob.as_ref().to_string());
+        self
+    }
+
     /// Configures what filename protobufs with no package definition are written to.
     pub fn default_module_name(mut self, name: impl AsRef<str>) -> Self {
         self.default_module_name = Some(name.as_ref().to_string());
         self
     }
 
+    /// Configures the prefix of the `NamedMessage::TYPE_URL` of the generated messages.
+    ///
+    /// This defaults to `type.googleapis.com`.
+    pub fn type_url_prefix(mut self, prefix: impl AsRef<str>) -> Self {
+        self.type_url_prefix = Some(prefix.as_ref().to_string());
+        self
+    }
+
+    /// Generate a `registry` module next to the root `mod.rs`, listing every generated message
+    /// so that it can be decoded, encoded and formatted by name through the
+    /// `grpc_build_core::MessageRegistry` trait.
+    ///
+    /// This defaults to `false`.
+    pub fn message_registry(mut self, enable: bool) -> Self {
+        self.message_registry = enable;
+        self
+    }
+
+    /// Write the encoded `FileDescriptorSet` of the protos into the output directory, and declare
+    /// it as a `FILE_DESCRIPTOR_SET` constant in the root `mod.rs`, e.g. to set up
+    /// `tonic-reflection`.
+    ///
+    /// This defaults to `false`.
+    pub fn embed_file_descriptor_set(mut self, enable: bool) -> Self {
+        self.embed_file_descriptor_set = enable;
+        self
+    }
+
+    /// Like [`Builder::embed_file_descriptor_set`], but declares a `FILE_DESCRIPTOR_SET` constant
+    /// in the module of every package, holding only the files of that package and their imports.
+    ///
+    /// This defaults to `false`.
+    pub fn embed_package_file_descriptor_sets(mut self, enable: bool) -> Self {
+        self.embed_package_file_descriptor_sets = enable;
+        self
+    }
+
     /// Enable or disable gRPC client code generation.
     pub fn build_client(mut self, enable: bool) -> Self {
         self.tonic = self.tonic.build_client(enable);
+        self.build_client = enable;
         self
     }
 
     /// Enable or disable gRPC server code generation.
     pub fn build_server(mut self, enable: bool) -> Self {
         self.tonic = self.tonic.build_server(enable);
+        self.build_server = enable;
         self
     }
 
@@ -89,6 +321,10 @@ impl Builder {
             proto_path.as_ref().to_string(),
             rust_path.as_ref().to_string(),
         );
+        self.extern_paths.push((
+            proto_path.as_ref().to_string(),
+            rust_path.as_ref().to_string(),
+        ));
         self
     }
 
@@ -98,7 +334,7 @@ impl Builder {
     pub fn field_attribute<P: AsRef<str>, A: AsRef<str>>(mut self, path: P, attribute: A) -> Self {
         self.prost
             .field_attribute(path.as_ref(), attribute.as_ref());
-        self
+        self.add_attribute("field_attribute", path, attribute)
     }
 
     /// Add additional attribute to matched messages, enums, and one-offs.
@@ -106,7 +342,7 @@ impl Builder {
     /// Passed directly to `prost_build::Config.type_attribute`.
     pub fn type_attribute<P: AsRef<str>, A: AsRef<str>>(mut self, path: P, attribute: A) -> Self {
         self.prost.type_attribute(path.as_ref(), attribute.as_ref());
-        self
+        self.add_attribute("type_attribute", path, attribute)
     }
 
     /// Add additional attribute to matched server `mod`s. Matches on the package name.
@@ -115,14 +351,18 @@ impl Builder {
         path: P,
         attribute: A,
     ) -> Self {
-        self.tonic = self.tonic.server_mod_attribute(path, attribute);
-        self
+        self.tonic = self
+            .tonic
+            .server_mod_attribute(path.as_ref(), attribute.as_ref());
+        self.add_attribute("server_mod_attribute", path, attribute)
     }
 
     /// Add additional attribute to matched service servers. Matches on the service name.
     pub fn server_attribute<P: AsRef<str>, A: AsRef<str>>(mut self, path: P, attribute: A) -> Self {
-        self.tonic = self.tonic.server_attribute(path, attribute);
-        self
+        self.tonic = self
+            .tonic
+            .server_attribute(path.as_ref(), attribute.as_ref());
+        self.add_attribute("server_attribute", path, attribute)
     }
 
     /// Add additional attribute to matched client `mod`s. Matches on the package name.
@@ -131,14 +371,18 @@ impl Builder {
         path: P,
         attribute: A,
     ) -> Self {
-        self.tonic = self.tonic.client_mod_attribute(path, attribute);
-        self
+        self.tonic = self
+            .tonic
+            .client_mod_attribute(path.as_ref(), attribute.as_ref());
+        self.add_attribute("client_mod_attribute", path, attribute)
     }
 
     /// Add additional attribute to matched service clients. Matches on the service name.
     pub fn client_attribute<P: AsRef<str>, A: AsRef<str>>(mut self, path: P, attribute: A) -> Self {
-        self.tonic = self.tonic.client_attribute(path, attribute);
-        self
+        self.tonic = self
+            .tonic
+            .client_attribute(path.as_ref(), attribute.as_ref());
+        self.add_attribute("client_attribute", path, attribute)
     }
 
     /// Configure Prost `protoc_args` build arguments.
@@ -157,17 +401,32 @@ impl Builder {
         if compile_well_known_types {
             self.prost.compile_well_known_types();
         };
+        self.compile_well_known_types |= compile_well_known_types;
         self
     }
 
     /// Configures the optional module filename for easy inclusion of all generated Rust files
     ///
-    /// If set, generates a file (inside the `OUT_DIR` or `out_dir()` as appropriate) which contains
-    /// a set of `pub mod XXX` statements combining to load all Rust files generated.  This can allow
-    /// for a shortcut where multiple related proto files have been compiled together resulting in
-    /// a semi-complex set of includes.
+    /// If set, generates a file at `path`, relative to the output directory, which includes the
+    /// root file and so loads all Rust files generated. This can allow for a shortcut where
+    /// multiple related proto files have been compiled together resulting in a semi-complex set of
+    /// includes.
     pub fn include_file(mut self, path: impl AsRef<Path>) -> Self {
-        self.prost.include_file(path.as_ref());
+        self.include_file = Some(path.as_ref().to_owned());
+        self
+    }
+
+    fn add_attribute(
+        mut self,
+        kind: &'static str,
+        path: impl AsRef<str>,
+        attribute: impl AsRef<str>,
+    ) -> Self {
+        self.attributes.push((
+            kind,
+            path.as_ref().to_owned(),
+            attribute.as_ref().to_owned(),
+        ));
         self
     }
 
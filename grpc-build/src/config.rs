@@ -0,0 +1,311 @@
+//! Reads the [`Builder`] options from a `grpc-build.toml` file, so that the same options can be
+//! shared by build scripts and the `grpc_build` binary.
+//!
+//! Every key is named after the [`Builder`] method it configures, and every key is optional:
+//!
+//! ```toml
+//! in_dirs = ["protos"]
+//! out_dir = "src/protogen"
+//! include_paths = ["third_party"]
+//! exclude = ["**/fixtures/**"]
+//! build_client = true
+//! build_server = false
+//! layout = "directory" # or "single-file"
+//! module_style = "mod-rs" # or "non-mod-rs"
+//!
+//! [extern_paths]
+//! ".google.protobuf" = "::pbjson_types"
+//!
+//! [type_attributes]
+//! "." = ["#[derive(serde::Serialize)]"]
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{Builder, Error, Layout, ModuleStyle};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    in_dirs: Vec<PathBuf>,
+    out_dir: Option<PathBuf>,
+    include_paths: Vec<PathBuf>,
+    include_only: Vec<String>,
+    exclude: Vec<String>,
+    force: Option<bool>,
+    incremental: Option<bool>,
+    follow_links: Option<bool>,
+    build_client: Option<bool>,
+    build_server: Option<bool>,
+    layout: Option<LayoutConfig>,
+    module_style: Option<ModuleStyleConfig>,
+    root_file_name: Option<String>,
+    format: Option<bool>,
+    rustfmt_path: Option<PathBuf>,
+    rustfmt_edition: Option<String>,
+    rustfmt_config_path: Option<PathBuf>,
+    default_module_name: Option<String>,
+    type_url_prefix: Option<String>,
+    message_registry: Option<bool>,
+    embed_file_descriptor_set: Option<bool>,
+    embed_package_file_descriptor_sets: Option<bool>,
+    compile_well_known_types: Option<bool>,
+    protoc_args: Vec<String>,
+    /// Relative to the output directory, like with [`Builder::include_file`]
+    include_file: Option<PathBuf>,
+    file_descriptor_set_path: Option<PathBuf>,
+    extern_paths: BTreeMap<String, String>,
+    // The attributes to add, by proto path
+    type_attributes: BTreeMap<String, Vec<String>>,
+    field_attributes: BTreeMap<String, Vec<String>>,
+    server_mod_attributes: BTreeMap<String, Vec<String>>,
+    server_attributes: BTreeMap<String, Vec<String>>,
+    client_mod_attributes: BTreeMap<String, Vec<String>>,
+    client_attributes: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum LayoutConfig {
+    Directory,
+    SingleFile,
+}
+
+impl From<LayoutConfig> for Layout {
+    fn from(layout: LayoutConfig) -> Self {
+        match layout {
+            LayoutConfig::Directory => Layout::Directory,
+            LayoutConfig::SingleFile => Layout::SingleFile,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ModuleStyleConfig {
+    NonModRs,
+    ModRs,
+}
+
+impl From<ModuleStyleConfig> for ModuleStyle {
+    fn from(style: ModuleStyleConfig) -> Self {
+        match style {
+            ModuleStyleConfig::NonModRs => ModuleStyle::NonModRs,
+            ModuleStyleConfig::ModRs => ModuleStyle::ModRs,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file at `path`. The relative paths it holds are resolved from the
+    /// directory of the file.
+    pub(crate) fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs_err::read_to_string(path).map_err(Error::io("read", path))?;
+        let mut config: Config = toml::from_str(&content).map_err(|err| Error::Config {
+            path: path.to_owned(),
+            message: err.to_string(),
+        })?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let resolve = |path: &mut PathBuf| *path = dir.join(&*path);
+        config.in_dirs.iter_mut().for_each(resolve);
+        config.include_paths.iter_mut().for_each(resolve);
+        config.out_dir.iter_mut().for_each(resolve);
+        // A bare program name is looked up in the `PATH` instead
+        config
+            .rustfmt_path
+            .iter_mut()
+            .filter(|path| path.components().count() > 1)
+            .for_each(resolve);
+        config.rustfmt_config_path.iter_mut().for_each(resolve);
+        config.file_descriptor_set_path.iter_mut().for_each(resolve);
+
+        Ok(config)
+    }
+
+    /// Sets the options of the config on `builder`, leaving the others as they are.
+    pub(crate) fn apply(self, mut builder: Builder) -> Builder {
+        builder = self.in_dirs.iter().fold(builder, Builder::in_dir);
+        builder = self
+            .include_paths
+            .iter()
+            .fold(builder, Builder::include_path);
+        builder = self
+            .include_only
+            .iter()
+            .fold(builder, Builder::include_only);
+        builder = self.exclude.iter().fold(builder, Builder::exclude);
+        builder = self.protoc_args.iter().fold(builder, Builder::protoc_arg);
+
+        if let Some(out_dir) = self.out_dir {
+            builder = builder.out_dir(out_dir);
+        }
+        if let Some(force) = self.force {
+            builder = builder.force(force);
+        }
+        if let Some(incremental) = self.incremental {
+            builder = builder.incremental(incremental);
+        }
+        if let Some(follow_links) = self.follow_links {
+            builder = builder.follow_links(follow_links);
+        }
+        if let Some(enable) = self.build_client {
+            builder = builder.build_client(enable);
+        }
+        if let Some(enable) = self.build_server {
+            builder = builder.build_server(enable);
+        }
+        if let Some(layout) = self.layout {
+            builder = builder.layout(layout.into());
+        }
+        if let Some(style) = self.module_style {
+            builder = builder.module_style(style.into());
+        }
+        if let Some(name) = self.root_file_name {
+            builder = builder.root_file_name(name);
+        }
+        if let Some(enable) = self.format {
+            builder = builder.format(enable);
+        }
+        if let Some(path) = self.rustfmt_path {
+            builder = builder.rustfmt_path(path);
+        }
+        if let Some(edition) = self.rustfmt_edition {
+            builder = builder.rustfmt_edition(edition);
+        }
+        if let Some(path) = self.rustfmt_config_path {
+            builder = builder.rustfmt_config_path(path);
+        }
+        if let Some(name) = self.default_module_name {
+            builder = builder.default_module_name(name);
+        }
+        if let Some(prefix) = self.type_url_prefix {
+            builder = builder.type_url_prefix(prefix);
+        }
+        if let Some(enable) = self.message_registry {
+            builder = builder.message_registry(enable);
+        }
+        if let Some(enable) = self.embed_file_descriptor_set {
+            builder = builder.embed_file_descriptor_set(enable);
+        }
+        if let Some(enable) = self.embed_package_file_descriptor_sets {
+            builder = builder.embed_package_file_descriptor_sets(enable);
+        }
+        if let Some(enable) = self.compile_well_known_types {
+            builder = builder.compile_well_known_types(enable);
+        }
+        if let Some(path) = self.include_file {
+            builder = builder.include_file(path);
+        }
+        if let Some(path) = self.file_descriptor_set_path {
+            builder = builder.file_descriptor_set_path(path);
+        }
+
+        for (proto_path, rust_path) in &self.extern_paths {
+            builder = builder.extern_path(proto_path, rust_path);
+        }
+        for (path, attribute) in attributes(&self.type_attributes) {
+            builder = builder.type_attribute(path, attribute);
+        }
+        for (path, attribute) in attributes(&self.field_attributes) {
+            builder = builder.field_attribute(path, attribute);
+        }
+        for (path, attribute) in attributes(&self.server_mod_attributes) {
+            builder = builder.server_mod_attribute(path, attribute);
+        }
+        for (path, attribute) in attributes(&self.server_attributes) {
+            builder = builder.server_attribute(path, attribute);
+        }
+        for (path, attribute) in attributes(&self.client_mod_attributes) {
+            builder = builder.client_mod_attribute(path, attribute);
+        }
+        for (path, attribute) in attributes(&self.client_attributes) {
+            builder = builder.client_attribute(path, attribute);
+        }
+        builder
+    }
+}
+
+fn attributes(by_path: &BTreeMap<String, Vec<String>>) -> impl Iterator<Item = (&str, &str)> {
+    by_path.iter().flat_map(|(path, attributes)| {
+        attributes
+            .iter()
+            .map(move |attribute| (path.as_str(), attribute.as_str()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_FILE: &str = "grpc-build.toml";
+
+    #[test]
+    fn parse_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs_err::write(
+            &path,
+            r##"
+in_dirs = ["protos"]
+out_dir = "/generated"
+build_server = false
+layout = "single-file"
+module_style = "mod-rs"
+rustfmt_path = "rustfmt"
+rustfmt_config_path = "config/rustfmt.toml"
+include_file = "protos.rs"
+
+[extern_paths]
+".google.protobuf" = "::pbjson_types"
+
+[type_attributes]
+"." = ["#[derive(Eq)]", "#[derive(Hash)]"]
+"##,
+        )
+        .unwrap();
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.in_dirs, [dir.path().join("protos")]);
+        assert_eq!(config.out_dir, Some("/generated".into()));
+        assert_eq!(config.build_server, Some(false));
+        assert_eq!(config.build_client, None);
+        assert_eq!(config.rustfmt_path, Some("rustfmt".into()));
+        assert_eq!(
+            config.rustfmt_config_path,
+            Some(dir.path().join("config/rustfmt.toml"))
+        );
+        assert!(matches!(config.layout, Some(LayoutConfig::SingleFile)));
+        assert!(matches!(
+            config.module_style,
+            Some(ModuleStyleConfig::ModRs)
+        ));
+        assert_eq!(config.extern_paths[".google.protobuf"], "::pbjson_types");
+        assert_eq!(
+            attributes(&config.type_attributes).collect::<Vec<_>>(),
+            [(".", "#[derive(Eq)]"), (".", "#[derive(Hash)]")]
+        );
+
+        let builder = config.apply(Builder::new());
+        assert!(!builder.build_server);
+        assert!(builder.build_client);
+        assert_eq!(builder.layout.module_style, ModuleStyle::ModRs);
+        assert_eq!(builder.include_file, Some("protos.rs".into()));
+    }
+
+    #[test]
+    fn reject_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        fs_err::write(&path, "in_dir = \"protos\"\n").unwrap();
+
+        let err = Config::from_file(&path).unwrap_err();
+        assert!(matches!(err, Error::Config { .. }), "{err}");
+        assert!(err.to_string().contains("in_dir"), "{err}");
+    }
+}
@@ -0,0 +1,735 @@
+//! Filters and prints the `FileDescriptorSet` of the protos, for the `describe` command.
+//!
+//! The set is decoded with `prost_reflect`, which keeps the custom options that `prost_types`
+//! drops, and each file is kept as it was encoded, so that the binary output is the one of
+//! `protoc`.
+
+use prost::Message;
+use prost_reflect::{
+    DescriptorPool, DynamicMessage, FileDescriptor, Kind, ReflectMessage, Value as ReflectValue,
+};
+use prost_types::{
+    field_descriptor_proto::{Label, Type},
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    FileDescriptorSet, ServiceDescriptorProto,
+};
+use serde_json::Value;
+
+/// A `FileDescriptorSet` with its files left encoded, which encodes back to the same bytes
+#[derive(Clone, PartialEq, Message)]
+struct EncodedFiles {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    file: Vec<Vec<u8>>,
+}
+
+/// The files of a `FileDescriptorSet`, along with the pool resolving their custom options.
+pub struct Descriptors {
+    pool: DescriptorPool,
+    /// Every file, with its `FileDescriptorProto` as it was encoded
+    files: Vec<(FileDescriptor, Vec<u8>)>,
+}
+
+impl Descriptors {
+    /// Decodes the set as `protoc` encoded it.
+    pub fn decode(buf: &[u8]) -> anyhow::Result<Self> {
+        let pool = DescriptorPool::decode(buf)?;
+        let files = EncodedFiles::decode(buf)?
+            .file
+            .into_iter()
+            .map(|encoded| {
+                let name = FileDescriptorProto::decode(&*encoded)?.name;
+                let file = pool
+                    .get_file_by_name(name.as_deref().unwrap_or_default())
+                    .expect("the pool holds every file of the set");
+                Ok((file, encoded))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { pool, files })
+    }
+
+    /// Encodes the files that are left, each one as it was.
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let files = EncodedFiles {
+            file: self
+                .files
+                .iter()
+                .map(|(_, encoded)| encoded.clone())
+                .collect(),
+        };
+        files.encode_to_vec()
+    }
+}
+
+/// Which files of the set to keep. Every kind of filter that is set has to match, which it does
+/// when any of its values matches.
+#[derive(Default)]
+pub struct Filter {
+    /// The names of the files, relative to their include path
+    pub files: Vec<String>,
+    /// The packages of the files, subpackages included
+    pub packages: Vec<String>,
+    /// The full names of messages, enums or services the files define
+    pub symbols: Vec<String>,
+}
+
+impl Filter {
+    pub fn apply(&self, descriptors: &mut Descriptors) {
+        descriptors
+            .files
+            .retain(|(file, _)| self.matches(file.file_descriptor_proto()));
+    }
+
+    fn matches(&self, file: &FileDescriptorProto) -> bool {
+        let file_matches =
+            self.files.is_empty() || self.files.iter().any(|name| name == file.name());
+        let package_matches = self.packages.is_empty()
+            || self
+                .packages
+                .iter()
+                .any(|package| is_in_package(file.package(), package));
+        let symbol_matches = self.symbols.is_empty() || {
+            let defined = symbols(file);
+            self.symbols.iter().any(|symbol| {
+                defined
+                    .iter()
+                    .any(|name| name == symbol.trim_start_matches('.'))
+            })
+        };
+
+        file_matches && package_matches && symbol_matches
+    }
+}
+
+fn is_in_package(package: &str, filter: &str) -> bool {
+    package == filter
+        || package
+            .strip_prefix(filter)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// The full names of the messages, enums and services defined in the file, nested ones included
+fn symbols(file: &FileDescriptorProto) -> Vec<String> {
+    let mut symbols = Vec::new();
+    for message in &file.message_type {
+        collect_message_symbols(file.package(), message, &mut symbols);
+    }
+    for enum_type in &file.enum_type {
+        symbols.push(full_name(file.package(), enum_type.name()));
+    }
+    for service in &file.service {
+        symbols.push(full_name(file.package(), service.name()));
+    }
+    symbols
+}
+
+fn collect_message_symbols(namespace: &str, message: &DescriptorProto, symbols: &mut Vec<String>) {
+    let name = full_name(namespace, message.name());
+    for nested in &message.nested_type {
+        collect_message_symbols(&name, nested, symbols);
+    }
+    for enum_type in &message.enum_type {
+        symbols.push(full_name(&name, enum_type.name()));
+    }
+    symbols.push(name);
+}
+
+fn full_name(namespace: &str, name: &str) -> String {
+    if namespace.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{namespace}.{name}")
+    }
+}
+
+/// Prints the set as an outline of the definitions of every file, e.g. `message a.B`, with their
+/// options.
+pub fn to_text(descriptors: &Descriptors) -> String {
+    let pool = &descriptors.pool;
+    let mut lines = Vec::new();
+    for (descriptor, _) in &descriptors.files {
+        let file = descriptor.file_descriptor_proto();
+        lines.push(format!("file {}", file.name()));
+        lines.push(format!("  syntax {}", syntax(file)));
+        if !file.package().is_empty() {
+            lines.push(format!("  package {}", file.package()));
+        }
+        for dependency in &file.dependency {
+            lines.push(format!("  import {dependency}"));
+        }
+        option_text(Some(descriptor.options()), 1, &mut lines);
+        for message in &file.message_type {
+            message_text(pool, file, file.package(), message, 1, &mut lines);
+        }
+        for enum_type in &file.enum_type {
+            enum_text(pool, file.package(), enum_type, 1, &mut lines);
+        }
+        extend_text(pool, file, file.package(), &file.extension, 1, &mut lines);
+        for service in &file.service {
+            service_text(pool, file.package(), service, &mut lines);
+        }
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// `proto2` or `proto3`, as files without a `syntax` are proto2
+fn syntax(file: &FileDescriptorProto) -> &str {
+    match file.syntax() {
+        "" => "proto2",
+        syntax => syntax,
+    }
+}
+
+fn message_text(
+    pool: &DescriptorPool,
+    file: &FileDescriptorProto,
+    namespace: &str,
+    message: &DescriptorProto,
+    depth: usize,
+    lines: &mut Vec<String>,
+) {
+    let name = full_name(namespace, message.name());
+    let descriptor = pool.get_message_by_name(&name);
+    let indent = "  ".repeat(depth);
+    lines.push(format!("{indent}message {name}"));
+    option_text(descriptor.as_ref().map(|d| d.options()), depth + 1, lines);
+
+    let field_options = |field: &FieldDescriptorProto| {
+        descriptor
+            .as_ref()
+            .and_then(|d| d.get_field_by_name(field.name()))
+            .map(|field| field.options())
+    };
+    let mut printed_oneofs = Vec::new();
+    for field in &message.field {
+        match oneof(field) {
+            None => lines.push(field_text(file, field, field_options(field), depth + 1)),
+            // The whole oneof is printed at its first field
+            Some(index) if !printed_oneofs.contains(&index) => {
+                printed_oneofs.push(index);
+                let decl = &message.oneof_decl[index];
+                lines.push(format!("{indent}  oneof {}", decl.name()));
+                let oneof_options = descriptor.as_ref().and_then(|d| {
+                    d.oneofs()
+                        .find(|oneof| oneof.name() == decl.name())
+                        .map(|oneof| oneof.options())
+                });
+                option_text(oneof_options, depth + 2, lines);
+                for field in &message.field {
+                    if oneof(field) == Some(index) {
+                        lines.push(field_text(file, field, field_options(field), depth + 2));
+                    }
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    for nested in &message.nested_type {
+        message_text(pool, file, &name, nested, depth + 1, lines);
+    }
+    for enum_type in &message.enum_type {
+        enum_text(pool, &name, enum_type, depth + 1, lines);
+    }
+    extend_text(pool, file, &name, &message.extension, depth + 1, lines);
+}
+
+/// The index of the oneof the field is declared in, leaving out the oneofs generated for proto3
+/// `optional` fields
+fn oneof(field: &FieldDescriptorProto) -> Option<usize> {
+    match field.oneof_index {
+        Some(index) if !field.proto3_optional() => Some(index as usize),
+        _ => None,
+    }
+}
+
+/// The field as it is declared, e.g. `optional string name = 1 [deprecated = true]`
+fn field_text(
+    file: &FileDescriptorProto,
+    field: &FieldDescriptorProto,
+    field_options: Option<DynamicMessage>,
+    depth: usize,
+) -> String {
+    let mut text = "  ".repeat(depth);
+    if let Some(label) = label(file, field) {
+        text.push_str(label);
+        text.push(' ');
+    }
+    text.push_str(&format!(
+        "{} {} = {}",
+        field_type(field),
+        field.name(),
+        field.number()
+    ));
+
+    let mut options = Vec::new();
+    if let Some(default) = &field.default_value {
+        let default = match field.r#type() {
+            Type::String | Type::Bytes => format!("{default:?}"),
+            _ => default.clone(),
+        };
+        options.push(format!("default = {default}"));
+    }
+    if let Some(json_name) = &field.json_name {
+        if *json_name != default_json_name(field.name()) {
+            options.push(format!("json_name = {json_name:?}"));
+        }
+    }
+    options.extend(options_of(field_options));
+    if !options.is_empty() {
+        text.push_str(&format!(" [{}]", options.join(", ")));
+    }
+    text
+}
+
+/// `repeated` or `required`, or `optional` for the fields with explicit presence outside of a
+/// oneof: every such proto2 field, but only the proto3 fields declared `optional`
+fn label(file: &FileDescriptorProto, field: &FieldDescriptorProto) -> Option<&'static str> {
+    match field.label() {
+        Label::Repeated => Some("repeated"),
+        Label::Required => Some("required"),
+        Label::Optional if field.proto3_optional() => Some("optional"),
+        Label::Optional if syntax(file) == "proto2" && oneof(field).is_none() => Some("optional"),
+        Label::Optional => None,
+    }
+}
+
+/// The full name of the message or enum, or the scalar type like `string`
+fn field_type(field: &FieldDescriptorProto) -> String {
+    match field.type_name.as_deref() {
+        Some(type_name) => type_name.to_owned(),
+        None => field
+            .r#type()
+            .as_str_name()
+            .trim_start_matches("TYPE_")
+            .to_lowercase(),
+    }
+}
+
+/// The JSON name protoc gives the field when it has no `json_name` option, e.g. `fooBar` for
+/// `foo_bar`
+fn default_json_name(name: &str) -> String {
+    let mut json_name = String::with_capacity(name.len());
+    let mut capitalize = false;
+    for char in name.chars() {
+        if char == '_' {
+            capitalize = true;
+        } else if capitalize {
+            json_name.push(char.to_ascii_uppercase());
+            capitalize = false;
+        } else {
+            json_name.push(char);
+        }
+    }
+    json_name
+}
+
+fn enum_text(
+    pool: &DescriptorPool,
+    namespace: &str,
+    enum_type: &EnumDescriptorProto,
+    depth: usize,
+    lines: &mut Vec<String>,
+) {
+    let name = full_name(namespace, enum_type.name());
+    let descriptor = pool.get_enum_by_name(&name);
+    let indent = "  ".repeat(depth);
+    lines.push(format!("{indent}enum {name}"));
+    option_text(descriptor.as_ref().map(|d| d.options()), depth + 1, lines);
+    for value in &enum_type.value {
+        let mut text = format!("{indent}  {} = {}", value.name(), value.number());
+        let options = options_of(
+            descriptor
+                .as_ref()
+                .and_then(|d| d.get_value_by_name(value.name()))
+                .map(|value| value.options()),
+        );
+        if !options.is_empty() {
+            text.push_str(&format!(" [{}]", options.join(", ")));
+        }
+        lines.push(text);
+    }
+}
+
+/// Prints the extensions declared in the scope of `namespace` as `extend` blocks, one for each
+/// message they extend.
+fn extend_text(
+    pool: &DescriptorPool,
+    file: &FileDescriptorProto,
+    namespace: &str,
+    extensions: &[FieldDescriptorProto],
+    depth: usize,
+    lines: &mut Vec<String>,
+) {
+    let indent = "  ".repeat(depth);
+    let mut printed_extendees = Vec::new();
+    for extension in extensions {
+        let extendee = extension.extendee();
+        if printed_extendees.contains(&extendee) {
+            continue;
+        }
+        printed_extendees.push(extendee);
+
+        lines.push(format!("{indent}extend {extendee}"));
+        for field in extensions
+            .iter()
+            .filter(|field| field.extendee() == extendee)
+        {
+            let options = pool
+                .get_extension_by_name(&full_name(namespace, field.name()))
+                .map(|extension| extension.options());
+            lines.push(field_text(file, field, options, depth + 1));
+        }
+    }
+}
+
+fn service_text(
+    pool: &DescriptorPool,
+    namespace: &str,
+    service: &ServiceDescriptorProto,
+    lines: &mut Vec<String>,
+) {
+    let name = full_name(namespace, service.name());
+    let descriptor = pool.get_service_by_name(&name);
+    lines.push(format!("  service {name}"));
+    option_text(descriptor.as_ref().map(|d| d.options()), 2, lines);
+    for method in &service.method {
+        let stream = |streaming| if streaming { "stream " } else { "" };
+        lines.push(format!(
+            "    rpc {}({}{}) returns ({}{})",
+            method.name(),
+            stream(method.client_streaming()),
+            method.input_type(),
+            stream(method.server_streaming()),
+            method.output_type()
+        ));
+        let method_options = descriptor.as_ref().and_then(|d| {
+            d.methods()
+                .find(|descriptor| descriptor.name() == method.name())
+                .map(|descriptor| descriptor.options())
+        });
+        option_text(method_options, 3, lines);
+    }
+}
+
+/// Prints every option that is set as an `option name = value` line.
+fn option_text(options: Option<DynamicMessage>, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    for option in options_of(options) {
+        lines.push(format!("{indent}option {option}"));
+    }
+}
+
+/// Every option that is set, as `name = value` with the value in the protobuf text format. The
+/// custom options are named like in the protos, e.g. `(my.pkg.option) = value`.
+fn options_of(options: Option<DynamicMessage>) -> Vec<String> {
+    let Some(options) = options else {
+        return Vec::new();
+    };
+    let fields = options
+        .fields()
+        .map(|(field, value)| (field.name().to_owned(), field.kind(), value));
+    let extensions = options.extensions().map(|(extension, value)| {
+        (
+            format!("({})", extension.full_name()),
+            extension.kind(),
+            value,
+        )
+    });
+    fields
+        .chain(extensions)
+        .map(|(name, kind, value)| {
+            let value = match (kind, value) {
+                (Kind::Enum(enum_type), ReflectValue::EnumNumber(number)) => enum_type
+                    .get_value(*number)
+                    .map_or_else(|| number.to_string(), |value| value.name().to_owned()),
+                _ => value.to_string(),
+            };
+            format!("{name} = {value}")
+        })
+        .collect()
+}
+
+/// Converts the set to its canonical protobuf JSON mapping, e.g. `{"file": [{"name": ...}]}`,
+/// which keeps everything the descriptors hold, the custom options included.
+pub fn to_json(descriptors: &Descriptors) -> Value {
+    // The custom options extend the `descriptor.proto` the set imports to declare them, so they
+    // are only decoded with its messages
+    let descriptor = descriptors
+        .pool
+        .get_message_by_name("google.protobuf.FileDescriptorSet")
+        .unwrap_or_else(|| FileDescriptorSet::default().descriptor());
+    let file_descriptor_set = DynamicMessage::decode(descriptor, &*descriptors.encode_to_vec())
+        .expect("the set was decoded already");
+    serde_json::to_value(file_descriptor_set).expect("descriptors can be converted to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::{
+        descriptor_proto::ExtensionRange, method_options::IdempotencyLevel,
+        EnumValueDescriptorProto, EnumValueOptions, FieldOptions, FileOptions,
+        MethodDescriptorProto, MethodOptions, OneofDescriptorProto,
+    };
+
+    use super::*;
+
+    fn field(name: &str, number: i32, label: Label, r#type: Type) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.into()),
+            number: Some(number),
+            label: Some(label.into()),
+            r#type: Some(r#type.into()),
+            json_name: Some(default_json_name(name)),
+            ..Default::default()
+        }
+    }
+
+    fn descriptors() -> Descriptors {
+        let request = DescriptorProto {
+            name: Some("Request".into()),
+            field: vec![
+                field("names", 1, Label::Repeated, Type::String),
+                FieldDescriptorProto {
+                    oneof_index: Some(0),
+                    proto3_optional: Some(true),
+                    ..field("page_size", 2, Label::Optional, Type::Int32)
+                },
+                FieldDescriptorProto {
+                    oneof_index: Some(1),
+                    json_name: Some("ID".into()),
+                    options: Some(FieldOptions {
+                        deprecated: Some(true),
+                        ..Default::default()
+                    }),
+                    ..field("id", 3, Label::Optional, Type::String)
+                },
+                field("verbose", 4, Label::Optional, Type::Bool),
+                FieldDescriptorProto {
+                    oneof_index: Some(1),
+                    ..field("slug", 5, Label::Optional, Type::String)
+                },
+            ],
+            nested_type: vec![DescriptorProto {
+                name: Some("Nested".into()),
+                ..Default::default()
+            }],
+            oneof_decl: vec![
+                OneofDescriptorProto {
+                    name: Some("_page_size".into()),
+                    ..Default::default()
+                },
+                OneofDescriptorProto {
+                    name: Some("key".into()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let service = ServiceDescriptorProto {
+            name: Some("Service".into()),
+            method: vec![MethodDescriptorProto {
+                name: Some("Call".into()),
+                input_type: Some(".a.b.Request".into()),
+                output_type: Some(".common.Limits".into()),
+                server_streaming: Some(true),
+                options: Some(MethodOptions {
+                    idempotency_level: Some(IdempotencyLevel::NoSideEffects.into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let status = EnumDescriptorProto {
+            name: Some("Status".into()),
+            value: vec![
+                EnumValueDescriptorProto {
+                    name: Some("OK".into()),
+                    number: Some(0),
+                    ..Default::default()
+                },
+                EnumValueDescriptorProto {
+                    name: Some("FAILED".into()),
+                    number: Some(1),
+                    options: Some(EnumValueOptions {
+                        deprecated: Some(true),
+                        ..Default::default()
+                    }),
+                },
+            ],
+            ..Default::default()
+        };
+        let limits = DescriptorProto {
+            name: Some("Limits".into()),
+            field: vec![
+                FieldDescriptorProto {
+                    default_value: Some("10".into()),
+                    ..field("max", 1, Label::Optional, Type::Int32)
+                },
+                FieldDescriptorProto {
+                    default_value: Some("a \"b\"".into()),
+                    ..field("unit", 2, Label::Required, Type::String)
+                },
+            ],
+            extension_range: vec![ExtensionRange {
+                start: Some(100),
+                end: Some(200),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let note = FieldDescriptorProto {
+            extendee: Some(".common.Limits".into()),
+            json_name: None,
+            ..field("note", 100, Label::Optional, Type::String)
+        };
+
+        let file_descriptor_set = FileDescriptorSet {
+            file: vec![
+                FileDescriptorProto {
+                    name: Some("common.proto".into()),
+                    package: Some("common".into()),
+                    message_type: vec![limits],
+                    enum_type: vec![status],
+                    extension: vec![note],
+                    ..Default::default()
+                },
+                FileDescriptorProto {
+                    name: Some("a/b.proto".into()),
+                    package: Some("a.b".into()),
+                    dependency: vec!["common.proto".into()],
+                    message_type: vec![request],
+                    service: vec![service],
+                    options: Some(FileOptions {
+                        java_package: Some("com.a.b".into()),
+                        ..Default::default()
+                    }),
+                    syntax: Some("proto3".into()),
+                    ..Default::default()
+                },
+            ],
+        };
+        Descriptors::decode(&file_descriptor_set.encode_to_vec()).unwrap()
+    }
+
+    #[test]
+    fn filter_files() {
+        let names = |filter: Filter| {
+            let mut descriptors = descriptors();
+            filter.apply(&mut descriptors);
+            descriptors
+                .files
+                .iter()
+                .map(|(file, _)| file.name().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(Filter::default()).len(), 2);
+        let packages = |packages: &[&str]| Filter {
+            packages: packages
+                .iter()
+                .map(|package| (*package).to_owned())
+                .collect(),
+            ..Filter::default()
+        };
+        assert_eq!(names(packages(&["a"])), ["a/b.proto"]);
+        assert!(names(packages(&["a.b.c", "comm"])).is_empty());
+
+        let symbols = Filter {
+            symbols: vec![".a.b.Request.Nested".into(), "common.Status".into()],
+            ..Filter::default()
+        };
+        assert_eq!(names(symbols).len(), 2);
+
+        let both = Filter {
+            files: vec!["common.proto".into()],
+            packages: vec!["a".into()],
+            ..Filter::default()
+        };
+        assert!(names(both).is_empty());
+    }
+
+    #[test]
+    fn print_text() {
+        assert_eq!(
+            to_text(&descriptors()),
+            "\
+file common.proto
+  syntax proto2
+  package common
+  message common.Limits
+    optional int32 max = 1 [default = 10]
+    required string unit = 2 [default = \"a \\\"b\\\"\"]
+  enum common.Status
+    OK = 0
+    FAILED = 1 [deprecated = true]
+  extend .common.Limits
+    optional string note = 100
+file a/b.proto
+  syntax proto3
+  package a.b
+  import common.proto
+  option java_package = \"com.a.b\"
+  message a.b.Request
+    repeated string names = 1
+    optional int32 page_size = 2
+    oneof key
+      string id = 3 [json_name = \"ID\", deprecated = true]
+      string slug = 5
+    bool verbose = 4
+    message a.b.Request.Nested
+  service a.b.Service
+    rpc Call(.a.b.Request) returns (stream .common.Limits)
+      option idempotency_level = NO_SIDE_EFFECTS
+"
+        );
+    }
+
+    #[test]
+    fn print_json() {
+        let json = to_json(&descriptors());
+        let request = &json["file"][1]["messageType"][0];
+        assert_eq!(request["field"][0]["type"], "TYPE_STRING");
+        assert_eq!(request["field"][1]["proto3Optional"], true);
+        assert_eq!(request["field"][2]["jsonName"], "ID");
+        assert_eq!(request["field"][2]["options"]["deprecated"], true);
+        assert_eq!(request["oneofDecl"][1]["name"], "key");
+        assert_eq!(request["nestedType"][0]["name"], "Nested");
+        assert_eq!(json["file"][1]["syntax"], "proto3");
+        assert_eq!(json["file"][1]["options"]["javaPackage"], "com.a.b");
+        assert_eq!(
+            json["file"][1]["service"][0]["method"][0]["options"]["idempotencyLevel"],
+            "NO_SIDE_EFFECTS"
+        );
+        assert_eq!(
+            json["file"][0]["messageType"][0]["field"][0]["defaultValue"],
+            "10"
+        );
+        assert_eq!(
+            json["file"][0]["extension"][0]["extendee"],
+            ".common.Limits"
+        );
+    }
+
+    #[test]
+    fn encode_the_files_as_they_were() {
+        let mut descriptors = descriptors();
+        let encoded = descriptors.encode_to_vec();
+        assert_eq!(
+            Descriptors::decode(&encoded).unwrap().encode_to_vec(),
+            encoded
+        );
+
+        Filter {
+            files: vec!["a/b.proto".into()],
+            ..Filter::default()
+        }
+        .apply(&mut descriptors);
+        let filtered = FileDescriptorSet::decode(&*descriptors.encode_to_vec()).unwrap();
+        assert_eq!(filtered.file.len(), 1);
+        assert_eq!(filtered.file[0].name(), "a/b.proto");
+    }
+}
@@ -0,0 +1,178 @@
+//! Embeds the encoded `FileDescriptorSet` into the generated code, as `FILE_DESCRIPTOR_SET`
+//! constants in the root `mod.rs` and optionally in every package module.
+//!
+//! The sets are written to `.bin` files included next to the modules, except with
+//! [`Layout::SingleFile`], where they are inlined as byte strings so that the file is
+//! self-contained.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+};
+
+use fs_err::OpenOptions;
+use prost::Message;
+use prost_build::Module;
+use prost_types::{FileDescriptorProto, FileDescriptorSet};
+use std::io::Write;
+
+use crate::{
+    base,
+    tree::{laid_out_module_file, Layout, LayoutOptions, ModuleStyle},
+    Error,
+};
+
+/// The file the whole `FileDescriptorSet` is written to, next to the root file
+const FILE_NAME: &str = "file_descriptor_set.bin";
+
+/// Writes the whole `FileDescriptorSet` next to the root file, and declares it there.
+pub(crate) fn embed(
+    out_dir: &Path,
+    file_descriptor_set: &[u8],
+    options: &LayoutOptions,
+) -> Result<(), Error> {
+    let value = match options.layout {
+        Layout::Directory => {
+            let path = out_dir.join(FILE_NAME);
+            fs_err::write(&path, file_descriptor_set).map_err(Error::io("write", &path))?;
+            include_bytes(FILE_NAME)
+        }
+        Layout::SingleFile => byte_string(file_descriptor_set),
+    };
+    append_constant(
+        &out_dir.join(&options.root_file_name),
+        "The encoded `FileDescriptorSet` of every compiled proto, imports included",
+        &value,
+    )
+}
+
+/// Writes a `FileDescriptorSet` with only the files of each package and their imports where the
+/// package module will end up in the layout, and declares it in the module. This runs on the
+/// files generated by prost, before they are laid out. The packages prost wrote no module for,
+/// like the well-known types resolved through an extern path, are skipped.
+pub(crate) fn embed_per_package(
+    out_dir: &Path,
+    file_descriptor_set: &FileDescriptorSet,
+    generated: &HashSet<String>,
+    modules: &HashSet<Module>,
+    default_module_name: &str,
+    options: &LayoutOptions,
+) -> Result<(), Error> {
+    let files_by_name = file_descriptor_set
+        .file
+        .iter()
+        .map(|file| (file.name(), file))
+        .collect::<HashMap<_, _>>();
+
+    let mut packages = BTreeMap::<&str, HashSet<&str>>::new();
+    for file in &file_descriptor_set.file {
+        let module = Module::from_protobuf_package_name(file.package());
+        if generated.contains(file.name()) && modules.contains(&module) {
+            let files = packages.entry(file.package()).or_default();
+            collect_dependencies(file, &files_by_name, files);
+        }
+    }
+
+    let modules = packages
+        .keys()
+        .map(|package| Module::from_protobuf_package_name(package))
+        .collect::<Vec<_>>();
+
+    for (package, files) in &packages {
+        // Keep the order of the whole set, which lists the imports before the files using them
+        let package_set = FileDescriptorSet {
+            file: file_descriptor_set
+                .file
+                .iter()
+                .filter(|file| files.contains(file.name()))
+                .cloned()
+                .collect(),
+        };
+
+        let module = Module::from_protobuf_package_name(package);
+        let module_file = out_dir.join(module.to_file_name_or(default_module_name));
+        let value = match options.layout {
+            // Next to the file the module is moved to
+            Layout::Directory => {
+                let has_submodules = modules.iter().any(|other| is_submodule(other, &module));
+                let mut path = laid_out_module_file(&module, default_module_name);
+                if has_submodules && options.module_style == ModuleStyle::ModRs {
+                    path = path.with_extension("").join("mod.rs");
+                }
+                let stem = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .expect("module names are valid UTF-8");
+                let file_name = format!("{stem}.{FILE_NAME}");
+                let bin = out_dir.join(path.with_file_name(&file_name));
+
+                let dir = base::parent_dir(&bin);
+                fs_err::create_dir_all(dir).map_err(Error::io("create", dir))?;
+                fs_err::write(&bin, package_set.encode_to_vec())
+                    .map_err(Error::io("write", &bin))?;
+                include_bytes(&file_name)
+            }
+            Layout::SingleFile => byte_string(&package_set.encode_to_vec()),
+        };
+        append_constant(
+            &module_file,
+            &format!("The encoded `FileDescriptorSet` of the `{package}` package and its imports"),
+            &value,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn collect_dependencies<'a>(
+    file: &'a FileDescriptorProto,
+    files_by_name: &HashMap<&str, &'a FileDescriptorProto>,
+    collected: &mut HashSet<&'a str>,
+) {
+    if !collected.insert(file.name()) {
+        return;
+    }
+    for dependency in &file.dependency {
+        if let Some(dependency) = files_by_name.get(dependency.as_str()) {
+            collect_dependencies(dependency, files_by_name, collected);
+        }
+    }
+}
+
+/// Whether `module` is nested in `parent`. The module of protos without a package is not the
+/// parent of anything, as it gets its own name.
+fn is_submodule(module: &Module, parent: &Module) -> bool {
+    !parent.is_empty()
+        && module.len() > parent.len()
+        && module.parts().zip(parent.parts()).all(|(a, b)| a == b)
+}
+
+/// Includes the file `file_name`, next to the module file.
+fn include_bytes(file_name: &str) -> String {
+    format!("include_bytes!(\"{file_name}\")")
+}
+
+/// A byte string literal holding `bytes`.
+fn byte_string(bytes: &[u8]) -> String {
+    let mut literal = String::from("b\"");
+    literal.extend(
+        bytes
+            .iter()
+            .flat_map(|byte| std::ascii::escape_default(*byte))
+            .map(char::from),
+    );
+    literal.push('"');
+    literal
+}
+
+fn append_constant(module_file: &Path, doc: &str, value: &str) -> Result<(), Error> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(module_file)
+        .map_err(Error::io("open", module_file))?;
+    writeln!(
+        file,
+        "\n/// {doc}\npub const FILE_DESCRIPTOR_SET: &[u8] = {value};"
+    )
+    .map_err(Error::io("write to", module_file))
+}
@@ -0,0 +1,161 @@
+//! Contains the [`Diagnostic`] type, parsed from the errors reported for invalid protos.
+
+use std::{fmt, path::PathBuf};
+
+/// An error or warning reported for a `.proto` file, e.g. a syntax error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The file, as it was reported: usually relative to its include path
+    pub path: PathBuf,
+    /// The 1-based line, if the diagnostic points into the file
+    pub line: Option<u32>,
+    /// The 1-based column, if the diagnostic points into the file
+    pub column: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+            if let Some(column) = self.column {
+                write!(f, ":{column}")?;
+            }
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+#[cfg(any(not(feature = "pure-rust"), test))]
+/// Parses the lines `protoc` writes to stderr, which look like `path.proto:8:1: message`, or
+/// `path.proto: message` when they're about the file as a whole. Other lines are skipped.
+pub(crate) fn parse_protoc(stderr: &str) -> Vec<Diagnostic> {
+    stderr.lines().filter_map(parse_protoc_line).collect()
+}
+
+/// The lines of `protoc`'s stderr that [`parse_protoc`] skips, like
+/// `Could not make proto path relative: ...`.
+pub(crate) fn unparsed_protoc_lines(stderr: &str) -> impl Iterator<Item = &str> {
+    stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && parse_protoc_line(line).is_none())
+}
+
+fn parse_protoc_line(line: &str) -> Option<Diagnostic> {
+    // Looking for the extension rather than the first colon handles Windows paths
+    let (path, rest) = line.split_once(".proto:")?;
+    if path.contains(": ") {
+        // A message mentioning a proto, like `Could not make proto path relative: a.proto: ...`
+        return None;
+    }
+    let (line, column, message) = parse_location(rest);
+
+    Some(Diagnostic {
+        path: format!("{path}.proto").into(),
+        line,
+        column,
+        message: message.trim().to_owned(),
+    })
+}
+
+/// Splits the `8:1:` location off of the start of `rest`, if there is one.
+fn parse_location(rest: &str) -> (Option<u32>, Option<u32>, &str) {
+    let mut parts = rest.splitn(3, ':');
+    let line = parts.next().and_then(|line| line.trim().parse().ok());
+    let column = parts.next().and_then(|column| column.trim().parse().ok());
+    match (line, column, parts.next()) {
+        (Some(line), Some(column), Some(message)) => (Some(line), Some(column), message),
+        _ => (None, None, rest),
+    }
+}
+
+/// Finds the error of the pure rust parser that points into a file, which looks like
+/// ``error in `path.proto`: at 8:1: message``. Other errors have no location.
+#[cfg(feature = "pure-rust")]
+pub(crate) fn parse_parser_error(error: &anyhow::Error) -> Vec<Diagnostic> {
+    let parsed = error
+        .chain()
+        .find_map(|cause| parse_parser_message(&cause.to_string()));
+
+    parsed.into_iter().collect()
+}
+
+#[cfg(feature = "pure-rust")]
+fn parse_parser_message(message: &str) -> Option<Diagnostic> {
+    message.strip_prefix("error in `").and_then(|rest| {
+        let (path, rest) = rest.split_once("`: ")?;
+        let (line, column, message) = match rest.strip_prefix("at ") {
+            Some(located) => parse_location(located),
+            None => (None, None, rest),
+        };
+        Some(Diagnostic {
+            path: path.into(),
+            line,
+            column,
+            message: message.trim().to_owned(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_protoc_stderr() {
+        let stderr = "\
+invalid/invalid.proto:8:1: Expected \";\".
+C:\\protos\\unused.proto: warning: Import other.proto is unused.
+Could not make proto path relative: missing.proto: No such file or directory
+";
+        let diagnostics = parse_protoc(stderr);
+
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic {
+                    path: "invalid/invalid.proto".into(),
+                    line: Some(8),
+                    column: Some(1),
+                    message: "Expected \";\".".into(),
+                },
+                Diagnostic {
+                    path: "C:\\protos\\unused.proto".into(),
+                    line: None,
+                    column: None,
+                    message: "warning: Import other.proto is unused.".into(),
+                },
+            ]
+        );
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "invalid/invalid.proto:8:1: Expected \";\"."
+        );
+        assert_eq!(
+            unparsed_protoc_lines(stderr).collect::<Vec<_>>(),
+            ["Could not make proto path relative: missing.proto: No such file or directory"]
+        );
+    }
+
+    #[cfg(feature = "pure-rust")]
+    #[test]
+    fn parse_pure_rust_error() {
+        let error = anyhow::anyhow!("error in `protos/invalid.proto`: at 8:1: expecting `;`")
+            .context("using pure parser");
+        let diagnostics = parse_parser_error(&error);
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                path: "protos/invalid.proto".into(),
+                line: Some(8),
+                column: Some(1),
+                message: "expecting `;`".into(),
+            }]
+        );
+
+        let error = anyhow::anyhow!("protobuf path `a.proto` is not found");
+        assert!(parse_parser_error(&error).is_empty());
+    }
+}
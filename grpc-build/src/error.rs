@@ -0,0 +1,221 @@
+//! Contains the [`Error`] type returned by the library.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{base::DirDiff, Diagnostic};
+
+/// The ways building the protos can fail
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// `protoc` could not be found, usually because it is not installed
+    ProtocMissing { path: PathBuf, source: io::Error },
+    /// `protoc` rejected the protos, e.g. because of a syntax error
+    ProtocFailed {
+        status: std::process::ExitStatus,
+        stderr: String,
+        /// The errors and warnings parsed from `stderr`
+        diagnostics: Vec<Diagnostic>,
+    },
+    /// The protos could not be parsed in-process
+    #[cfg(feature = "pure-rust")]
+    Parse {
+        /// The location of the error, if it points into a file
+        diagnostics: Vec<Diagnostic>,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// An input directory has no `.proto` files
+    NoProtosFound { path: PathBuf },
+    /// Every `.proto` file was filtered out by the `include_only` and `exclude` patterns
+    AllProtosFiltered,
+    /// The output directory exists, and `force` was not set
+    OutDirExists { path: PathBuf },
+    /// No output directory was set, and `$OUT_DIR` isn't set either
+    NoOutDir,
+    /// No input directory was passed or configured
+    NoInDir,
+    /// The config file is invalid, e.g. it has an unknown key
+    Config { path: PathBuf, message: String },
+    /// An `include_only` or `exclude` pattern is not a valid glob
+    InvalidGlob {
+        pattern: String,
+        source: globset::Error,
+    },
+    /// The `FileDescriptorSet` produced for the protos could not be decoded
+    InvalidFileDescriptorSet(prost::DecodeError),
+    /// Generating the code for the protos failed
+    Codegen(io::Error),
+    /// A generated file could not be formatted
+    Format { path: PathBuf, message: String },
+    /// A file generated by prost could not be parsed to lay it out
+    GeneratedCode { path: PathBuf, message: String },
+    /// The module of a proto file, or an item in it, would clash with another one
+    ModuleClash {
+        module: String,
+        file: String,
+        /// The other proto file declaring it, or `None` if it is generated by `grpc_build` itself
+        other: Option<String>,
+    },
+    /// The generated code differs from the code in the output directory, see
+    /// [`Builder::check`](crate::Builder::check)
+    OutOfDate { out_dir: PathBuf, diff: DirDiff },
+    /// A filesystem operation failed
+    Io {
+        /// What was being done, e.g. `write`
+        operation: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+}
+
+impl Error {
+    /// The errors and warnings reported for the `.proto` files, if the protos were rejected.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            Error::ProtocFailed { diagnostics, .. } => diagnostics,
+            #[cfg(feature = "pure-rust")]
+            Error::Parse { diagnostics, .. } => diagnostics,
+            _ => &[],
+        }
+    }
+
+    /// Returns a function wrapping an [`io::Error`] that happened while doing `operation` on
+    /// `path`, for use with `map_err`.
+    pub(crate) fn io(
+        operation: &'static str,
+        path: impl AsRef<Path>,
+    ) -> impl FnOnce(io::Error) -> Self {
+        let path = path.as_ref().to_owned();
+        move |source| Error::Io {
+            operation,
+            path,
+            source,
+        }
+    }
+
+    /// Returns a function wrapping an error that happened while walking `dir`, which points at
+    /// the entry that couldn't be read if there is one, for use with `map_err`.
+    pub(crate) fn walk(dir: &Path) -> impl FnOnce(walkdir::Error) -> Self + '_ {
+        move |err| {
+            let path = err.path().unwrap_or(dir).to_owned();
+            Error::io("read", path)(err.into())
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ProtocMissing { path, .. } => write!(
+                f,
+                "failed to invoke protoc at {} (hint: https://docs.rs/prost-build/#sourcing-protoc)",
+                path.display()
+            ),
+            Error::ProtocFailed {
+                status,
+                stderr,
+                diagnostics,
+            } => {
+                write!(f, "protoc returned a non-zero exit status: {}", status)?;
+                if diagnostics.is_empty() {
+                    write!(f, "\n{}", stderr.trim())
+                } else {
+                    write_diagnostics(f, diagnostics)?;
+                    // The lines that aren't about a proto, e.g. a missing import path
+                    for line in crate::diagnostic::unparsed_protoc_lines(stderr) {
+                        write!(f, "\n{}", line)?;
+                    }
+                    Ok(())
+                }
+            }
+            #[cfg(feature = "pure-rust")]
+            Error::Parse { diagnostics, .. } => {
+                write!(f, "failed to parse the protos")?;
+                write_diagnostics(f, diagnostics)
+            }
+            Error::NoProtosFound { path } => {
+                write!(f, "no .proto files found in {}", path.display())
+            }
+            Error::AllProtosFiltered => write!(f, "all of the .proto files were filtered out"),
+            Error::OutDirExists { path } => {
+                write!(f, "the output directory already exists: {}", path.display())
+            }
+            Error::NoOutDir => write!(f, "could not determine $OUT_DIR"),
+            Error::NoInDir => write!(f, "no input directory to find the .proto files in"),
+            Error::Config { path, message } => {
+                write!(f, "invalid config file {}: {}", path.display(), message)
+            }
+            Error::InvalidGlob { pattern, .. } => write!(f, "invalid glob pattern: {}", pattern),
+            Error::InvalidFileDescriptorSet(_) => write!(f, "invalid FileDescriptorSet"),
+            Error::Codegen(_) => write!(f, "failed to generate the code"),
+            Error::Format { path, message } => {
+                write!(f, "failed to format {}: {}", path.display(), message)
+            }
+            Error::GeneratedCode { path, message } => {
+                write!(f, "failed to parse the generated {}: {}", path.display(), message)
+            }
+            Error::ModuleClash {
+                module,
+                file,
+                other: None,
+            } => write!(
+                f,
+                "the `{}` module of {} would clash with the one generated by grpc_build",
+                module, file
+            ),
+            Error::ModuleClash {
+                module,
+                file,
+                other: Some(other),
+            } => write!(
+                f,
+                "`{}` would be declared both by {} and by {}",
+                module, file, other
+            ),
+            Error::OutOfDate { out_dir, diff } => write!(
+                f,
+                "the generated code in {} is out of date:\n{}",
+                out_dir.display(),
+                diff
+            ),
+            Error::Io {
+                operation, path, ..
+            } => write!(f, "failed to {} {}", operation, path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ProtocMissing { source, .. } => Some(source),
+            #[cfg(feature = "pure-rust")]
+            Error::Parse { source, .. } => Some(source.as_ref()),
+            Error::InvalidGlob { source, .. } => Some(source),
+            Error::InvalidFileDescriptorSet(source) => Some(source),
+            Error::Codegen(source) => Some(source),
+            Error::Io { source, .. } => Some(source),
+            Error::ProtocFailed { .. }
+            | Error::NoProtosFound { .. }
+            | Error::AllProtosFiltered
+            | Error::OutDirExists { .. }
+            | Error::NoOutDir
+            | Error::NoInDir
+            | Error::Config { .. }
+            | Error::Format { .. }
+            | Error::GeneratedCode { .. }
+            | Error::ModuleClash { .. }
+            | Error::OutOfDate { .. } => None,
+        }
+    }
+}
+
+fn write_diagnostics(f: &mut fmt::Formatter<'_>, diagnostics: &[Diagnostic]) -> fmt::Result {
+    for diagnostic in diagnostics {
+        write!(f, "\n{}", diagnostic)?;
+    }
+    Ok(())
+}
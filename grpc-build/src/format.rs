@@ -0,0 +1,250 @@
+//! Formats the generated files, with `rustfmt` or, if it isn't installed, with `prettyplease`.
+
+use std::{
+    ffi::OsString,
+    io::Write,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use walkdir::WalkDir;
+
+use crate::{manifest::Fingerprint, Error};
+
+/// The `rustfmt` options
+#[derive(Debug, Clone)]
+pub(crate) struct Rustfmt {
+    /// When unset, `$RUSTFMT` or `rustfmt` is used, falling back to `prettyplease` if neither
+    /// is installed
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) edition: String,
+    pub(crate) config_path: Option<PathBuf>,
+}
+
+impl Default for Rustfmt {
+    fn default() -> Self {
+        Self {
+            path: None,
+            edition: "2021".to_owned(),
+            config_path: None,
+        }
+    }
+}
+
+impl Rustfmt {
+    fn program(&self) -> OsString {
+        match &self.path {
+            Some(path) => path.clone().into(),
+            None => std::env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into()),
+        }
+    }
+
+    /// Formats the file in place. The file is passed through stdin so that `rustfmt` doesn't
+    /// format the modules it declares as well, which are formatted on their own.
+    fn format(&self, program: &OsString, file: &Path) -> Result<(), Error> {
+        let source = fs_err::read(file).map_err(Error::io("read", file))?;
+
+        let mut cmd = Command::new(program);
+        cmd.arg("--edition").arg(&self.edition);
+        if let Some(config_path) = &self.config_path {
+            cmd.arg("--config-path").arg(config_path);
+        }
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(Error::io("run rustfmt on", file))?;
+
+        // rustfmt reads the whole input before writing anything, so this can't deadlock
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(&source)
+            .map_err(Error::io("run rustfmt on", file))?;
+        let output = child
+            .wait_with_output()
+            .map_err(Error::io("run rustfmt on", file))?;
+
+        if !output.status.success() {
+            return Err(Error::Format {
+                path: file.to_owned(),
+                message: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            });
+        }
+        fs_err::write(file, output.stdout).map_err(Error::io("write", file))
+    }
+
+    /// Adds the `rustfmt` options and version to the fingerprint of the build, along with the
+    /// contents of its config file.
+    pub(crate) fn add_to_fingerprint(&self, fingerprint: &mut Fingerprint) {
+        let program = self.program();
+        fingerprint
+            .add("rustfmt_path", program.as_encoded_bytes())
+            .add("rustfmt_edition", &self.edition);
+        if let Some(config_path) = &self.config_path {
+            fingerprint
+                .add(
+                    "rustfmt_config_path",
+                    config_path.as_os_str().as_encoded_bytes(),
+                )
+                .add(
+                    "rustfmt_config",
+                    fs_err::read(config_path).unwrap_or_default(),
+                );
+        }
+        // Empty when it isn't installed, and the files are formatted with `prettyplease`
+        let version = Command::new(&program)
+            .arg("--version")
+            .output()
+            .map(|output| output.stdout)
+            .unwrap_or_default();
+        fingerprint.add("rustfmt_version", version);
+    }
+}
+
+fn is_installed(program: &OsString) -> bool {
+    Command::new(program)
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+fn prettyplease(file: &Path) -> Result<(), Error> {
+    let source = fs_err::read_to_string(file).map_err(Error::io("read", file))?;
+    let syntax = syn::parse_file(&source).map_err(|err| Error::Format {
+        path: file.to_owned(),
+        message: err.to_string(),
+    })?;
+    fs_err::write(file, prettyplease::unparse(&syntax)).map_err(Error::io("write", file))
+}
+
+/// Formats every `.rs` file in `dir`, in parallel. The whole directory is formatted even if some
+/// of the files fail, and the error of the first one in path order is returned.
+pub(crate) fn format_dir(dir: &Path, rustfmt: &Rustfmt) -> Result<(), Error> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dir) {
+        let entry = entry.map_err(Error::walk(dir))?;
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|ext| ext == "rs") {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+
+    let program = rustfmt.program();
+    // A rustfmt that was explicitly configured has to be there
+    if rustfmt.path.is_some() || is_installed(&program) {
+        for_each_parallel(&files, |file| rustfmt.format(&program, file))
+    } else {
+        for_each_parallel(&files, prettyplease)
+    }
+}
+
+fn for_each_parallel(
+    files: &[PathBuf],
+    f: impl Fn(&Path) -> Result<(), Error> + Sync,
+) -> Result<(), Error> {
+    let workers = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(files.len());
+    let next = AtomicUsize::new(0);
+
+    let mut results = thread::scope(|scope| {
+        let workers = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    while let Some(file) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        results.push((file, f(file)));
+                    }
+                    results
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("formatting should not panic"))
+            .collect::<Vec<_>>()
+    });
+
+    results.sort_by_key(|(file, _)| *file);
+    results.into_iter().try_for_each(|(_, result)| result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNFORMATTED: &str = "pub mod a;\npub struct Foo { pub bar : u32 , }\n";
+
+    fn write_files(dir: &Path) {
+        fs_err::create_dir_all(dir.join("a")).unwrap();
+        fs_err::write(dir.join("mod.rs"), UNFORMATTED).unwrap();
+        fs_err::write(dir.join("a.rs"), "pub mod b;").unwrap();
+        fs_err::write(dir.join("a/b.rs"), "fn  baz ( ) { }").unwrap();
+    }
+
+    #[test]
+    fn formats_every_file() {
+        if !is_installed(&Rustfmt::default().program()) {
+            eprintln!("rustfmt isn't installed, skipping");
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path());
+
+        format_dir(dir.path(), &Rustfmt::default()).unwrap();
+
+        let read = |path| fs_err::read_to_string(dir.path().join(path)).unwrap();
+        assert_eq!(
+            read("mod.rs"),
+            "pub mod a;\npub struct Foo {\n    pub bar: u32,\n}\n"
+        );
+        assert_eq!(read("a.rs"), "pub mod b;\n");
+        assert_eq!(read("a/b.rs"), "fn baz() {}\n");
+    }
+
+    #[test]
+    fn falls_back_to_prettyplease() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path());
+
+        for_each_parallel(&[dir.path().join("mod.rs")], prettyplease).unwrap();
+
+        let formatted = fs_err::read_to_string(dir.path().join("mod.rs")).unwrap();
+        assert_eq!(
+            formatted,
+            "pub mod a;\npub struct Foo {\n    pub bar: u32,\n}\n"
+        );
+    }
+
+    #[test]
+    fn reports_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        fs_err::write(dir.path().join("a.rs"), "fn {").unwrap();
+        fs_err::write(dir.path().join("b.rs"), "fn  b ( ) { }").unwrap();
+
+        let err = for_each_parallel(
+            &[dir.path().join("a.rs"), dir.path().join("b.rs")],
+            prettyplease,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Format { ref path, .. } if path.ends_with("a.rs")));
+        // The other files are still formatted
+        let formatted = fs_err::read_to_string(dir.path().join("b.rs")).unwrap();
+        assert_eq!(formatted, "fn b() {}\n");
+
+        let rustfmt = Rustfmt {
+            path: Some(dir.path().join("missing-rustfmt")),
+            ..Rustfmt::default()
+        };
+        let err = format_dir(dir.path(), &rustfmt).unwrap_err();
+        assert!(matches!(err, Error::Io { .. }), "{err}");
+    }
+}
@@ -0,0 +1,54 @@
+//! The conversions prost uses for the identifiers it generates, so that ours match.
+
+use heck::{ToSnakeCase, ToUpperCamelCase};
+
+pub(crate) fn to_snake(s: &str) -> String {
+    sanitize_identifier(s.to_snake_case())
+}
+
+pub(crate) fn to_upper_camel(s: &str) -> String {
+    sanitize_identifier(s.to_upper_camel_case())
+}
+
+/// Makes `ident` a valid identifier: keywords become raw identifiers, or get an underscore suffix
+/// when they can't be raw, and an underscore is prepended to names starting with a digit.
+pub(crate) fn sanitize_identifier(ident: String) -> String {
+    match ident.as_str() {
+        "as" | "break" | "const" | "continue" | "else" | "enum" | "false" | "fn" | "for" | "if"
+        | "impl" | "in" | "let" | "loop" | "match" | "mod" | "move" | "mut" | "pub" | "ref"
+        | "return" | "static" | "struct" | "trait" | "true" | "type" | "unsafe" | "use"
+        | "where" | "while" | "dyn" | "abstract" | "become" | "box" | "do" | "final" | "macro"
+        | "override" | "priv" | "typeof" | "unsized" | "virtual" | "yield" | "async" | "await"
+        | "try" => format!("r#{ident}"),
+        "_" | "super" | "self" | "Self" | "extern" | "crate" => format!("{ident}_"),
+        s if s.starts_with(|c: char| c.is_numeric()) => format!("_{ident}"),
+        _ => ident,
+    }
+}
+
+/// The name of the file or directory of a module declared as `ident`, which rustc looks up
+/// without the `r#` of raw identifiers.
+pub(crate) fn module_file_name(ident: &str) -> &str {
+    ident.strip_prefix("r#").unwrap_or(ident)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_module_names() {
+        for (name, ident, file_name) in [
+            ("v1", "v1", "v1"),
+            ("type", "r#type", "type"),
+            ("async", "r#async", "async"),
+            ("self", "self_", "self_"),
+            ("1beta", "_1beta", "_1beta"),
+        ] {
+            assert_eq!(sanitize_identifier(name.to_owned()), ident);
+            // Sanitizing is idempotent, as the names from prost are already sanitized
+            assert_eq!(sanitize_identifier(ident.to_owned()), ident);
+            assert_eq!(module_file_name(ident), file_name);
+        }
+    }
+}
@@ -1,90 +1,638 @@
-use anyhow::{anyhow, Context, Ok, Result};
+use manifest::{Fingerprint, Manifest};
 use prost::Message;
-use prost_build::{protoc_from_env, protoc_include_from_env, Module};
-use prost_types::{FileDescriptorProto, FileDescriptorSet};
-use std::{collections::HashMap, path::Path, process::Command};
+#[cfg(not(feature = "pure-rust"))]
+use prost_build::protoc_from_env;
+use prost_build::{protoc_include_from_env, Module};
+use prost_types::{DescriptorProto, EnumDescriptorProto, FileDescriptorProto, FileDescriptorSet};
+#[cfg(not(feature = "pure-rust"))]
+use std::process::Command;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 pub mod base;
 mod builder;
+mod config;
+mod descriptor;
+mod diagnostic;
+mod error;
+mod format;
+mod ident;
+mod manifest;
+mod merge;
+mod plan;
+mod registry;
+mod service;
 pub mod tree;
+mod watch;
 pub use builder::Builder;
+pub use diagnostic::Diagnostic;
+pub use error::Error;
+pub use plan::{Plan, PlannedProto};
+pub use tree::{Layout, ModuleStyle};
+pub use watch::Watcher;
+
+/// The `.proto` files to compile, along with the paths their imports are resolved from.
+struct Inputs {
+    protos: Vec<PathBuf>,
+    includes: Vec<PathBuf>,
+    /// The input protos as named in the `FileDescriptorSet`, i.e. relative to their include path
+    names: HashSet<String>,
+    /// The protos that were discovered but filtered out, named like `names`
+    excluded: HashSet<String>,
+}
+
+/// The outcome of compiling the protos.
+struct Compiled {
+    /// Every proto that went into the build, imports included
+    sources: Vec<PathBuf>,
+    /// The protos that code was generated for, as named in the `FileDescriptorSet`
+    generated: HashSet<String>,
+    /// The modules prost wrote a file for
+    modules: HashSet<Module>,
+    /// The protos declaring the items of each package
+    origins: merge::Origins,
+}
+
+/// The code generated into a staging directory.
+struct Generated {
+    /// Every proto that went into the build, imports included
+    sources: Vec<PathBuf>,
+    /// The encoded file descriptor set, if it is to be written outside of the output directory
+    file_descriptor_set: Option<(PathBuf, Vec<u8>)>,
+}
 
 impl Builder {
-    pub fn build(self, in_dir: impl AsRef<Path>) -> Result<(), anyhow::Error> {
+    pub fn build(self, in_dir: impl AsRef<Path>) -> Result<(), Error> {
+        self.build_all([in_dir])
+    }
+
+    /// Like [`Builder::build_all`], with only the input directories added with
+    /// [`Builder::in_dir`], e.g. by [`Builder::from_config_file`].
+    pub fn build_configured(self) -> Result<(), Error> {
+        self.build_all(None::<PathBuf>)
+    }
+
+    /// Like [`Builder::build`], but compiles the protos of several input directories in one pass.
+    pub fn build_all<I>(self, in_dirs: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let in_dirs = self.collect_in_dirs(in_dirs)?;
+
         let out_dir = self.get_out_dir()?;
+        let inputs = self.get_inputs(&in_dirs)?;
+
+        let fingerprint = self.incremental.then(|| self.fingerprint());
+        if let Some(fingerprint) = &fingerprint {
+            if let Some(manifest) = Manifest::read(&out_dir) {
+                let up_to_date = manifest.is_up_to_date(fingerprint, &inputs.protos, &out_dir)
+                    && self
+                        .file_descriptor_set_path
+                        .as_ref()
+                        .map_or(true, |path| path.is_file());
+
+                if up_to_date {
+                    if self.emit_rerun_if_changed {
+                        let dirs = in_dirs
+                            .iter()
+                            .chain(&self.include_paths)
+                            .chain(&self.config_file);
+                        emit_rerun_if_changed(dirs, manifest.sources());
+                    }
+                    return Ok(());
+                }
+            }
+        }
+
         if !self.force && out_dir.exists() {
-            return Err(anyhow!(
-                "the output directory already exists: {}",
-                out_dir.display()
-            ));
+            return Err(Error::OutDirExists { path: out_dir });
+        }
+
+        // Everything is generated in a staging directory next to the output directory, which
+        // only replaces the previous output once the build succeeded.
+        let out_parent = base::parent_dir(&out_dir);
+        fs_err::create_dir_all(out_parent).map_err(Error::io("create", out_parent))?;
+        let staging = tempfile::Builder::new()
+            .prefix(".grpc-build-staging")
+            .tempdir_in(out_parent)
+            .map_err(Error::io("create a staging directory in", out_parent))?;
+
+        let generated = self.generate(&in_dirs, &inputs, &out_dir, staging.path())?;
+
+        if let Some(fingerprint) = fingerprint {
+            Manifest::new(
+                fingerprint,
+                &inputs.protos,
+                generated.sources,
+                staging.path(),
+            )?
+            .write(staging.path())?;
         }
 
-        base::prepare_out_dir(&out_dir).context("failed to prepare out dir")?;
+        // Written next to where it goes before the output is replaced, so that once it is, only
+        // moving the file in place is left
+        let file_descriptor_set = generated
+            .file_descriptor_set
+            .map(|(path, buf)| stage_file(&path, &buf).map(|file| (path, file)))
+            .transpose()?;
 
-        match self.file_descriptor_set_path.clone() {
-            Some(file_descriptor_path) => {
-                self.compile(in_dir.as_ref(), &out_dir, &file_descriptor_path)
-                    .context("failed to compile the protos")?;
-            }
-            None => {
-                // Create a temporary directory to host the file descriptor set.
-                // The directory gets cleaned when compilation ends.
-                let tmp = tempfile::Builder::new()
-                    .prefix("grpc-build")
-                    .tempdir()
-                    .context("failed to get tempdir")?;
-                let file_descriptor_path = tmp.path().join("grpc-descriptor-set");
-
-                self.compile(in_dir.as_ref(), &out_dir, &file_descriptor_path)
-                    .context("failed to compile the protos")?;
-            }
+        base::replace_out_dir(staging.path(), &out_dir)?;
+
+        if let Some((path, file)) = file_descriptor_set {
+            file.persist(&path)
+                .map_err(|err| Error::io("write file descriptors to", &path)(err.error))?;
         }
 
-        base::refactor(out_dir).context("failed to refactor the protos")?;
+        Ok(())
+    }
+
+    /// Checks that the code in the output directory is what building `in_dir` would generate,
+    /// without touching it. The error lists the files that would be added, removed or changed.
+    pub fn check(self, in_dir: impl AsRef<Path>) -> Result<(), Error> {
+        self.check_all([in_dir])
+    }
+
+    /// Like [`Builder::check`], for the protos of several input directories.
+    pub fn check_all<I>(self, in_dirs: I) -> Result<(), Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let in_dirs = self.collect_in_dirs(in_dirs)?;
+
+        let out_dir = self.get_out_dir()?;
+        let inputs = self.get_inputs(&in_dirs)?;
+
+        let staging = tempfile::Builder::new()
+            .prefix("grpc-build")
+            .tempdir()
+            .map_err(Error::io("create", std::env::temp_dir()))?;
+        self.generate(&in_dirs, &inputs, &out_dir, staging.path())?;
+
+        let diff = base::compare_dirs(staging.path(), &out_dir)?;
+        if !diff.is_empty() {
+            return Err(Error::OutOfDate { out_dir, diff });
+        }
 
         Ok(())
     }
 
+    /// Like [`Builder::check_all`], with only the input directories added with
+    /// [`Builder::in_dir`].
+    pub fn check_configured(self) -> Result<(), Error> {
+        self.check_all(None::<PathBuf>)
+    }
+
+    /// The input directories added with [`Builder::in_dir`], followed by `in_dirs`.
+    fn collect_in_dirs<I>(&self, in_dirs: I) -> Result<Vec<PathBuf>, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let in_dirs = self
+            .in_dirs
+            .iter()
+            .cloned()
+            .chain(in_dirs.into_iter().map(|in_dir| in_dir.as_ref().to_owned()))
+            .collect::<Vec<_>>();
+
+        if in_dirs.is_empty() {
+            return Err(Error::NoInDir);
+        }
+        Ok(in_dirs)
+    }
+
+    /// Compiles the protos of `in_dir` into a `FileDescriptorSet`, imports included, without
+    /// generating anything. `protoc` is invoked as it is for a build, or the pure rust parser is
+    /// used with the `pure-rust` feature.
+    pub fn file_descriptor_set(self, in_dir: impl AsRef<Path>) -> Result<FileDescriptorSet, Error> {
+        self.file_descriptor_set_all([in_dir])
+    }
+
+    /// Like [`Builder::file_descriptor_set`], for the protos of several input directories.
+    pub fn file_descriptor_set_all<I>(self, in_dirs: I) -> Result<FileDescriptorSet, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let in_dirs = self.collect_in_dirs(in_dirs)?;
+        let inputs = self.get_inputs(&in_dirs)?;
+        self.read_descriptors(&inputs)
+    }
+
+    /// Like [`Builder::file_descriptor_set_all`], with only the input directories added with
+    /// [`Builder::in_dir`].
+    pub fn file_descriptor_set_configured(self) -> Result<FileDescriptorSet, Error> {
+        self.file_descriptor_set_all(None::<PathBuf>)
+    }
+
+    /// Like [`Builder::file_descriptor_set`], but returns the set as `protoc` (or the pure rust
+    /// parser) encoded it. Unlike the decoded `FileDescriptorSet`, it keeps the custom options,
+    /// which can be decoded with e.g. `prost_reflect::DescriptorPool::decode`.
+    pub fn encoded_file_descriptor_set(self, in_dir: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
+        self.encoded_file_descriptor_set_all([in_dir])
+    }
+
+    /// Like [`Builder::encoded_file_descriptor_set`], for the protos of several input directories.
+    pub fn encoded_file_descriptor_set_all<I>(self, in_dirs: I) -> Result<Vec<u8>, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let in_dirs = self.collect_in_dirs(in_dirs)?;
+        let inputs = self.get_inputs(&in_dirs)?;
+        self.read_encoded_descriptors(&inputs)
+    }
+
+    /// Like [`Builder::encoded_file_descriptor_set_all`], with only the input directories added
+    /// with [`Builder::in_dir`].
+    pub fn encoded_file_descriptor_set_configured(self) -> Result<Vec<u8>, Error> {
+        self.encoded_file_descriptor_set_all(None::<PathBuf>)
+    }
+
+    /// Lists the protos that building `in_dir` would compile, along with the module and the file
+    /// generated for each, without generating anything.
+    pub fn plan(self, in_dir: impl AsRef<Path>) -> Result<Plan, Error> {
+        self.plan_all([in_dir])
+    }
+
+    /// Like [`Builder::plan`], for the protos of several input directories.
+    pub fn plan_all<I>(self, in_dirs: I) -> Result<Plan, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let in_dirs = self.collect_in_dirs(in_dirs)?;
+        let inputs = self.get_inputs(&in_dirs)?;
+        let file_descriptor_set = self.read_descriptors(&inputs)?;
+
+        let generated = file_descriptor_set
+            .file
+            .iter()
+            .filter(|file| self.is_generated(&inputs, file));
+        let default_module_name = self.default_module_name.as_deref().unwrap_or("_");
+        Ok(plan::plan(
+            &inputs,
+            generated,
+            default_module_name,
+            &self.layout,
+        ))
+    }
+
+    /// Like [`Builder::plan_all`], with only the input directories added with
+    /// [`Builder::in_dir`].
+    pub fn plan_configured(self) -> Result<Plan, Error> {
+        self.plan_all(None::<PathBuf>)
+    }
+
+    /// Generates the code into `staging`, laid out as it should be in `out_dir`.
+    fn generate(
+        self,
+        in_dirs: &[PathBuf],
+        inputs: &Inputs,
+        out_dir: &Path,
+        staging: &Path,
+    ) -> Result<Generated, Error> {
+        // Create a temporary directory to host the file descriptor set.
+        // The directory gets cleaned when compilation ends.
+        let tmp = tempfile::Builder::new()
+            .prefix("grpc-build")
+            .tempdir()
+            .map_err(Error::io("create", std::env::temp_dir()))?;
+        let file_descriptor_path = tmp.path().join("grpc-descriptor-set");
+
+        let file_descriptor_set_path = self.file_descriptor_set_path.clone();
+        let embed_file_descriptor_set = self.embed_file_descriptor_set;
+        let embed_package_file_descriptor_sets = self.embed_package_file_descriptor_sets;
+        let default_module_name = self.default_module_name.clone();
+        let layout = self.layout.clone();
+        let include_file = self.include_file.clone();
+        let rustfmt = self.format.then(|| self.rustfmt.clone());
+        let compiled = self.compile(in_dirs, inputs, staging, &file_descriptor_path)?;
+
+        if embed_package_file_descriptor_sets {
+            let buf = read_file_descriptor_set(&file_descriptor_path)?;
+            let file_descriptor_set =
+                FileDescriptorSet::decode(&*buf).map_err(Error::InvalidFileDescriptorSet)?;
+            descriptor::embed_per_package(
+                staging,
+                &file_descriptor_set,
+                &compiled.generated,
+                &compiled.modules,
+                default_module_name.as_deref().unwrap_or("_"),
+                &layout,
+            )?;
+        }
+
+        base::refactor_tree(staging, &layout, &compiled.origins)?;
+
+        if embed_file_descriptor_set {
+            let buf = read_file_descriptor_set(&file_descriptor_path)?;
+            descriptor::embed(staging, &buf, &layout)?;
+        }
+
+        if let Some(include_file) = include_file {
+            write_include_file(staging, out_dir, &include_file, &layout.root_file_name)?;
+        }
+
+        if let Some(rustfmt) = rustfmt {
+            format::format_dir(staging, &rustfmt)?;
+        }
+
+        // The file descriptor set is part of the output if it is written inside of it
+        let file_descriptor_set = match file_descriptor_set_path {
+            Some(path) => {
+                let buf = read_file_descriptor_set(&file_descriptor_path)?;
+                match path.strip_prefix(out_dir).ok() {
+                    Some(relative) => {
+                        write_file(&staging.join(relative), &buf)?;
+                        None
+                    }
+                    None => Some((path, buf)),
+                }
+            }
+            None => None,
+        };
+
+        Ok(Generated {
+            sources: compiled.sources,
+            file_descriptor_set,
+        })
+    }
+
+    /// Compiles the protos into `out_dir`.
     fn compile(
         self,
-        input_dir: &Path,
+        input_dirs: &[PathBuf],
+        inputs: &Inputs,
         out_dir: &Path,
         file_descriptor_path: &Path,
-    ) -> Result<(), anyhow::Error> {
-        self.run_protoc(input_dir.as_ref(), file_descriptor_path)
-            .context("failed to run protoc")?;
+    ) -> Result<Compiled, Error> {
+        self.parse(inputs, file_descriptor_path)?;
+
+        let buf = read_file_descriptor_set(file_descriptor_path)?;
+        let mut file_descriptor_set =
+            FileDescriptorSet::decode(&*buf).map_err(Error::InvalidFileDescriptorSet)?;
+
+        let sources = resolve_sources(inputs, &file_descriptor_set);
+        if self.emit_rerun_if_changed {
+            let dirs = input_dirs
+                .iter()
+                .chain(&self.include_paths)
+                .chain(&self.config_file);
+            emit_rerun_if_changed(dirs, sources.iter().map(PathBuf::as_path));
+        }
 
-        let buf = fs_err::read(file_descriptor_path).context("failed to read file descriptors")?;
-        let file_descriptor_set =
-            FileDescriptorSet::decode(&*buf).context("invalid FileDescriptorSet")?;
+        file_descriptor_set
+            .file
+            .retain(|file| self.is_generated(inputs, file));
 
-        self.generate_services(out_dir, file_descriptor_set)
-            .context("failed to generic tonic services")?;
-        Ok(())
+        let generated = file_descriptor_set
+            .file
+            .iter()
+            .map(|file| file.name().to_owned())
+            .collect();
+        let origins = merge::Origins::new(&file_descriptor_set.file);
+
+        let modules = self.generate_services(out_dir, file_descriptor_set)?;
+        Ok(Compiled {
+            sources,
+            generated,
+            modules,
+            origins,
+        })
     }
 
-    fn run_protoc(
-        &self,
-        input_dir: &Path,
-        file_descriptor_path: &Path,
-    ) -> Result<(), anyhow::Error> {
-        let protos = crate::base::get_protos(input_dir, self.follow_links).collect::<Vec<_>>();
+    /// Writes the `FileDescriptorSet` of the protos to `file_descriptor_path`, with `protoc` or
+    /// the pure rust parser.
+    fn parse(&self, inputs: &Inputs, file_descriptor_path: &Path) -> Result<(), Error> {
+        #[cfg(not(feature = "pure-rust"))]
+        let parsed = self.run_protoc(inputs, file_descriptor_path);
+        #[cfg(feature = "pure-rust")]
+        let parsed = self.run_parser(inputs, file_descriptor_path);
+        if let Err(err) = &parsed {
+            if self.emit_cargo_warnings {
+                emit_cargo_warnings(err.diagnostics());
+            }
+        }
+        parsed
+    }
+
+    /// Parses the protos into a `FileDescriptorSet`, like [`Builder::parse`], imports included.
+    fn read_descriptors(&self, inputs: &Inputs) -> Result<FileDescriptorSet, Error> {
+        let buf = self.read_encoded_descriptors(inputs)?;
+        FileDescriptorSet::decode(&*buf).map_err(Error::InvalidFileDescriptorSet)
+    }
+
+    /// Like [`Builder::read_descriptors`], without decoding the set.
+    fn read_encoded_descriptors(&self, inputs: &Inputs) -> Result<Vec<u8>, Error> {
+        let tmp = tempfile::Builder::new()
+            .prefix("grpc-build")
+            .tempdir()
+            .map_err(Error::io("create", std::env::temp_dir()))?;
+        let file_descriptor_path = tmp.path().join("grpc-descriptor-set");
+
+        self.parse(inputs, &file_descriptor_path)?;
+        read_file_descriptor_set(&file_descriptor_path)
+    }
+
+    /// Whether code is generated for `file` of the `FileDescriptorSet`. Files that were filtered
+    /// out or only pulled in from the include paths are needed to resolve the imports, but we
+    /// don't generate them. Neither does prost generate the packages it resolves through an extern
+    /// path, like the well-known types bundled with `protoc` unless they are compiled too.
+    fn is_generated(&self, inputs: &Inputs, file: &FileDescriptorProto) -> bool {
+        let name = file.name();
+        let is_own = inputs.names.contains(name)
+            || !(inputs.excluded.contains(name)
+                || self
+                    .include_paths
+                    .iter()
+                    .any(|include| include.join(name).is_file()));
+
+        is_own && !self.is_extern(&format!(".{}", file.package()))
+    }
+
+    /// Whether prost resolves the type or package with the fully qualified `path`, e.g.
+    /// `.google.protobuf.Timestamp`, through an extern path instead of generating it.
+    fn is_extern(&self, path: &str) -> bool {
+        let well_known = (!self.compile_well_known_types).then_some(".google.protobuf");
+        self.extern_paths
+            .iter()
+            .map(|(proto_path, _)| proto_path.as_str())
+            .chain(well_known)
+            .any(|extern_path| {
+                path.strip_prefix(extern_path)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            })
+    }
+
+    /// Hashes everything besides the protos themselves that affects the generated code: the
+    /// options set on the builder and the versions of the tools. The options that only affect
+    /// how the build runs, like `force`, are left out.
+    fn fingerprint(&self) -> String {
+        fn path(path: &Path) -> &[u8] {
+            path.as_os_str().as_encoded_bytes()
+        }
+        fn flag(enabled: bool) -> [u8; 1] {
+            [enabled.into()]
+        }
+
+        let mut fingerprint = Fingerprint::default();
+        fingerprint.add("grpc_build", env!("CARGO_PKG_VERSION"));
+        if let Some(include) = protoc_include_from_env() {
+            fingerprint.add("protoc_include", path(&include));
+        }
+        #[cfg(not(feature = "pure-rust"))]
+        fingerprint.add(
+            "protoc",
+            Command::new(protoc_from_env())
+                .arg("--version")
+                .output()
+                .map(|output| output.stdout)
+                .unwrap_or_default(),
+        );
+        #[cfg(feature = "pure-rust")]
+        fingerprint.add("parser", "pure-rust");
 
-        if protos.is_empty() {
-            return Err(anyhow!("no .proto files found in {}", input_dir.display()));
+        for arg in &self.protoc_args {
+            fingerprint.add("protoc_arg", path(Path::new(arg)));
+        }
+        for include in &self.include_paths {
+            fingerprint.add("include_path", path(include));
+        }
+        for glob in &self.include_only {
+            fingerprint.add("include_only", glob);
+        }
+        for glob in &self.exclude {
+            fingerprint.add("exclude", glob);
+        }
+        fingerprint.add("follow_links", flag(self.follow_links));
+
+        fingerprint
+            .add("build_client", flag(self.build_client))
+            .add("build_server", flag(self.build_server))
+            .add(
+                "compile_well_known_types",
+                flag(self.compile_well_known_types),
+            );
+        for (proto_path, rust_path) in &self.extern_paths {
+            fingerprint
+                .add("extern_path", proto_path)
+                .add("extern_path", rust_path);
+        }
+        for (kind, matched, attribute) in &self.attributes {
+            fingerprint.add(kind, matched).add(kind, attribute);
+        }
+        if let Some(include_file) = &self.include_file {
+            fingerprint.add("include_file", path(include_file));
+        }
+        if let Some(name) = &self.default_module_name {
+            fingerprint.add("default_module_name", name);
+        }
+        if let Some(prefix) = &self.type_url_prefix {
+            fingerprint.add("type_url_prefix", prefix);
+        }
+        if let Some(set_path) = &self.file_descriptor_set_path {
+            fingerprint.add("file_descriptor_set_path", path(set_path));
         }
+        fingerprint
+            .add("message_registry", flag(self.message_registry))
+            .add(
+                "embed_file_descriptor_set",
+                flag(self.embed_file_descriptor_set),
+            )
+            .add(
+                "embed_package_file_descriptor_sets",
+                flag(self.embed_package_file_descriptor_sets),
+            )
+            .add("layout", format!("{:?}", self.layout.layout))
+            .add("module_style", format!("{:?}", self.layout.module_style))
+            .add("root_file_name", &self.layout.root_file_name);
+
+        fingerprint.add("format", flag(self.format));
+        if self.format {
+            self.rustfmt.add_to_fingerprint(&mut fingerprint);
+        }
+
+        fingerprint.finish()
+    }
 
-        let compile_includes: &Path = match input_dir.parent() {
-            None => Path::new("."),
-            Some(parent) => parent,
+    /// Collects the `.proto` files to compile from the input directories. Each input directory is
+    /// included from its parent, followed by the configured include paths.
+    fn get_inputs(&self, input_dirs: &[PathBuf]) -> Result<Inputs, Error> {
+        let mut inputs = Inputs {
+            protos: Vec::new(),
+            includes: Vec::new(),
+            names: HashSet::new(),
+            excluded: HashSet::new(),
         };
 
+        let filter = crate::base::ProtoFilter::new(&self.include_only, &self.exclude)?;
+
+        for input_dir in input_dirs {
+            let compile_includes: &Path = match input_dir.parent() {
+                None => Path::new("."),
+                Some(parent) => parent,
+            };
+
+            let mut found = false;
+            for proto in crate::base::walk_protos(input_dir, self.follow_links) {
+                let proto = proto?;
+                found = true;
+
+                let name = proto
+                    .strip_prefix(compile_includes)
+                    .ok()
+                    .and_then(Path::to_str)
+                    .map(|name| name.replace('\\', "/"));
+                let relative = proto.strip_prefix(input_dir).unwrap_or(&proto);
+
+                if filter.is_match(relative) {
+                    inputs.names.extend(name);
+                    inputs.protos.push(proto);
+                } else {
+                    inputs.excluded.extend(name);
+                }
+            }
+
+            if !found {
+                return Err(Error::NoProtosFound {
+                    path: input_dir.clone(),
+                });
+            }
+            if !inputs
+                .includes
+                .iter()
+                .any(|include| include == compile_includes)
+            {
+                inputs.includes.push(compile_includes.to_owned());
+            }
+        }
+
+        inputs.includes.extend(self.include_paths.iter().cloned());
+
+        if inputs.protos.is_empty() {
+            return Err(Error::AllProtosFiltered);
+        }
+
+        Ok(inputs)
+    }
+
+    #[cfg(not(feature = "pure-rust"))]
+    fn run_protoc(&self, inputs: &Inputs, file_descriptor_path: &Path) -> Result<(), Error> {
         let mut cmd = Command::new(protoc_from_env());
         cmd.arg("--include_imports")
             .arg("--include_source_info")
             .arg("--descriptor_set_out")
             .arg(file_descriptor_path);
-        cmd.arg("--proto_path").arg(compile_includes);
+
+        for include in &inputs.includes {
+            cmd.arg("--proto_path").arg(include);
+        }
 
         if let Some(include) = protoc_include_from_env() {
             cmd.arg("--proto_path").arg(include);
@@ -94,46 +642,107 @@ impl Builder {
             cmd.arg(arg);
         }
 
-        for proto in &protos {
+        for proto in &inputs.protos {
             cmd.arg(proto);
         }
 
         eprintln!("Running {cmd:?}");
 
-        let output = cmd.output().context(
-            "failed to invoke protoc (hint: https://docs.rs/prost-build/#sourcing-protoc)",
-        )?;
+        let output = cmd.output().map_err(|source| {
+            if source.kind() == std::io::ErrorKind::NotFound {
+                Error::ProtocMissing {
+                    path: protoc_from_env(),
+                    source,
+                }
+            } else {
+                Error::io("run", protoc_from_env())(source)
+            }
+        })?;
 
         if !output.status.success() {
-            eprintln!(
-                "---protoc stderr---\n{}\n------",
-                String::from_utf8_lossy(&output.stderr).trim()
-            );
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            return Err(Error::ProtocFailed {
+                status: output.status,
+                diagnostics: diagnostic::parse_protoc(&stderr),
+                stderr,
+            });
+        }
 
-            return Err(anyhow!(
-                "protoc returned a non-zero exit status: {}",
-                output.status,
-            ));
+        Ok(())
+    }
+
+    /// Parses and links the protos in-process, writing the same `FileDescriptorSet` that
+    /// `protoc --include_imports` would. The `protoc_arg` options are not applicable here
+    /// and are ignored. Unlike with `protoc`, there is no source code info, so the generated code
+    /// has no doc comments.
+    #[cfg(feature = "pure-rust")]
+    fn run_parser(&self, inputs: &Inputs, file_descriptor_path: &Path) -> Result<(), Error> {
+        let mut parser = protobuf_parse::Parser::new();
+        parser
+            .pure()
+            .includes(&inputs.includes)
+            .inputs(&inputs.protos);
+
+        if let Some(include) = protoc_include_from_env() {
+            parser.include(include);
         }
 
+        // Unlike `Parser::file_descriptor_set`, this keeps the imported files as well
+        let parsed = parser.parse_and_typecheck().map_err(|err| Error::Parse {
+            diagnostics: diagnostic::parse_parser_error(&err),
+            source: err.into(),
+        })?;
+        let file_descriptor_set = protobuf::descriptor::FileDescriptorSet {
+            file: parsed.file_descriptors,
+            ..Default::default()
+        };
+
+        let buf = protobuf::Message::write_to_bytes(&file_descriptor_set).map_err(|err| {
+            Error::Parse {
+                diagnostics: Vec::new(),
+                source: err.into(),
+            }
+        })?;
+        fs_err::write(file_descriptor_path, buf)
+            .map_err(Error::io("write file descriptors to", file_descriptor_path))?;
+
         Ok(())
     }
 
+    /// Generates the code of the protos in `file_descriptor_set` into `out_dir`, and returns the
+    /// modules prost wrote a file for.
     fn generate_services(
         mut self,
         out_dir: &Path,
         file_descriptor_set: FileDescriptorSet,
-    ) -> Result<(), anyhow::Error> {
-        let service_generator = self.tonic.service_generator();
-        self.prost.service_generator(service_generator);
+    ) -> Result<HashSet<Module>, Error> {
+        // Add our NamedMessage and NamedEnum derives. Every message and enum is generated from
+        // our protos, so the derives apply to all of them, and each gets its name below.
+        let type_url_prefix = match self.type_url_prefix.as_deref() {
+            Some(prefix) => format!(" #[type_url_prefix = \"{prefix}\"]"),
+            None => String::new(),
+        };
+        self.prost.message_attribute(
+            ".",
+            format!("#[derive(::grpc_build_core::NamedMessage)]{type_url_prefix}"),
+        );
+        // This includes the enums of oneofs, which the derive skips
+        self.prost
+            .enum_attribute(".", "#[derive(::grpc_build_core::NamedEnum)]");
 
         let requests = file_descriptor_set
             .file
             .into_iter()
             .map(|descriptor| {
-                // Add our NamedMessage derive
-                for (name, annotation) in derive_named_messages(&descriptor) {
-                    self.prost.type_attribute(&name, annotation);
+                for (name, annotation) in named_messages(&descriptor) {
+                    self.prost.message_attribute(&name, annotation);
+                }
+                let enums = named_enums(&descriptor);
+                for (name, annotation) in enums.enums {
+                    self.prost.enum_attribute(&name, annotation);
+                }
+                for (name, annotation) in enums.values {
+                    self.prost.field_attribute(&name, annotation);
                 }
 
                 (
@@ -143,49 +752,242 @@ impl Builder {
             })
             .collect::<Vec<_>>();
 
+        let default_module_name = self.default_module_name.as_deref().unwrap_or("_");
         let file_names = requests
             .iter()
-            .map(|(module, _)| {
-                (
-                    module.clone(),
-                    module.to_file_name_or(self.default_module_name.as_deref().unwrap_or("_")),
-                )
-            })
+            .map(|(module, _)| (module.clone(), module.to_file_name_or(default_module_name)))
             .collect::<HashMap<Module, String>>();
 
-        let modules = self.prost.generate(requests)?;
+        // The root file is written over the files generated by prost
+        let root_file_name = &self.layout.root_file_name;
+        if let Some((_, descriptor)) = requests
+            .iter()
+            .find(|(module, _)| file_names.get(module) == Some(root_file_name))
+        {
+            return Err(Error::ModuleClash {
+                module: root_file_name.trim_end_matches(".rs").to_owned(),
+                file: descriptor.name().to_owned(),
+                other: None,
+            });
+        }
+
+        let registry = if self.message_registry {
+            Some(registry::generate(
+                &requests,
+                default_module_name,
+                |path| self.is_extern(path),
+            )?)
+        } else {
+            None
+        };
+
+        let service_generator = service::NamedServiceGenerator::new(
+            self.tonic.service_generator(),
+            self.build_client,
+            self.build_server,
+        );
+        self.prost.service_generator(Box::new(service_generator));
+
+        let modules = self.prost.generate(requests).map_err(Error::Codegen)?;
         for (module, content) in &modules {
             let file_name = file_names
                 .get(module)
                 .expect("every module should have a filename");
-            let output_path = out_dir.join(file_name);
+            let path = out_dir.join(file_name);
+            fs_err::write(&path, content).map_err(Error::io("write", &path))?;
+        }
 
-            let previous_content = fs_err::read(&output_path);
+        // Written next to the generated files, so that it is declared in the root file
+        if let Some(registry) = registry {
+            let path = out_dir.join(format!("{}.rs", registry::MODULE_NAME));
+            fs_err::write(&path, registry).map_err(Error::io("write", &path))?;
+        }
 
-            // only write the file if the contents have changed
-            if previous_content
-                .map(|previous_content| previous_content != content.as_bytes())
-                .unwrap_or(true)
-            {
-                fs_err::write(output_path, content)?;
-            }
+        Ok(modules.into_keys().collect())
+    }
+}
+
+/// Writes the file descriptor set, creating its directory if needed.
+fn write_file(path: &Path, buf: &[u8]) -> Result<(), Error> {
+    fs_err::create_dir_all(base::parent_dir(path))
+        .and_then(|_| fs_err::write(path, buf))
+        .map_err(Error::io("write file descriptors to", path))
+}
+
+/// Writes the include file at `path`, relative to the output directory, which includes the root
+/// file so that every generated module can be loaded with a single `include!`.
+fn write_include_file(
+    staging: &Path,
+    out_dir: &Path,
+    path: &Path,
+    root_file_name: &str,
+) -> Result<(), Error> {
+    let relative = path.strip_prefix(out_dir).unwrap_or(path);
+    if relative.is_absolute() {
+        return Err(Error::io("write the include file outside of", out_dir)(
+            std::io::ErrorKind::InvalidInput.into(),
+        ));
+    }
+
+    // `include!` resolves the path from the directory of the include file
+    let depth = relative.components().count().saturating_sub(1);
+    let root_file = format!("{}{root_file_name}", "../".repeat(depth));
+    let content = format!("// Generated with `grpc_build`\n\ninclude!(\"{root_file}\");\n");
+
+    let path = staging.join(relative);
+    let dir = base::parent_dir(&path);
+    fs_err::create_dir_all(dir)
+        .and_then(|_| fs_err::write(&path, content))
+        .map_err(Error::io("write", &path))
+}
+
+/// Writes the file descriptor set to a temporary file in the directory of `path`, creating it if
+/// needed, to be moved to `path` once the rest of the output is in place.
+fn stage_file(path: &Path, buf: &[u8]) -> Result<tempfile::NamedTempFile, Error> {
+    let dir = base::parent_dir(path);
+    fs_err::create_dir_all(dir).map_err(Error::io("create", dir))?;
+    let mut file = tempfile::Builder::new()
+        .prefix(".grpc-build-staging")
+        .tempfile_in(dir)
+        .map_err(Error::io("create a staging file in", dir))?;
+    file.write_all(buf)
+        .map_err(Error::io("write file descriptors to", file.path()))?;
+    Ok(file)
+}
+
+fn read_file_descriptor_set(path: &Path) -> Result<Vec<u8>, Error> {
+    fs_err::read(path).map_err(Error::io("read file descriptors from", path))
+}
+
+/// Finds the files that went into the `FileDescriptorSet`: the input protos, followed by the
+/// imports resolved from the include paths. Imports that are not found belong to the protoc
+/// installation.
+fn resolve_sources(inputs: &Inputs, file_descriptor_set: &FileDescriptorSet) -> Vec<PathBuf> {
+    let imports = file_descriptor_set
+        .file
+        .iter()
+        .filter(|file| !inputs.names.contains(file.name()))
+        .filter_map(|file| {
+            inputs
+                .includes
+                .iter()
+                .map(|include| include.join(file.name()))
+                .find(|path| path.is_file())
+        });
+
+    inputs.protos.iter().cloned().chain(imports).collect()
+}
+
+/// Tell cargo to rerun the build script when the input and include directories, any of the
+/// protos that went into the build, or the environment used to find `protoc` change.
+fn emit_rerun_if_changed<'a>(
+    dirs: impl Iterator<Item = &'a PathBuf>,
+    sources: impl Iterator<Item = &'a Path>,
+) {
+    let paths: BTreeSet<&Path> = dirs.map(PathBuf::as_path).chain(sources).collect();
+
+    for path in paths {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+    println!("cargo:rerun-if-env-changed=PROTOC");
+    println!("cargo:rerun-if-env-changed=PROTOC_INCLUDE");
+}
+
+/// Tell cargo to show the diagnostics of the protos as warnings, which unlike the output of the
+/// build script are shown to the user.
+fn emit_cargo_warnings(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        println!("cargo:warning={}", diagnostic);
+    }
+}
+
+/// Build the name annotations of all the messages in a file, nested ones included.
+fn named_messages(descriptor: &FileDescriptorProto) -> Vec<(String, String)> {
+    let mut names = Vec::new();
+    collect_named_messages(descriptor.package(), &descriptor.message_type, &mut names);
+    names
+}
+
+/// Collects the names of `messages` and all of their nested messages, skipping the map entries
+/// generated by `protoc`, which have no type of their own.
+fn collect_named_messages(
+    namespace: &str,
+    messages: &[DescriptorProto],
+    names: &mut Vec<(String, String)>,
+) {
+    for message in messages {
+        if message.options.as_ref().is_some_and(|o| o.map_entry()) {
+            continue;
         }
 
-        Ok(())
+        let full_name = fully_qualified_name(namespace, message.name());
+        // Fully qualified, so that it can't match messages of other packages. prost applies it to
+        // the nested messages as well, which the derive tells apart by keeping the longest name.
+        names.push((
+            format!(".{full_name}"),
+            format!("#[name = \"{full_name}\"]"),
+        ));
+
+        collect_named_messages(&full_name, &message.nested_type, names);
     }
 }
 
-/// Build annotations for the top-level messages in a file,
-fn derive_named_messages(
-    descriptor: &FileDescriptorProto,
-) -> impl Iterator<Item = (String, String)> + '_ {
-    let namespace = descriptor.package();
-    descriptor.message_type.iter().map(|message| {
+/// Annotations for the enums of a file and their values, which carry the proto name of each.
+#[derive(Default)]
+struct EnumAnnotations {
+    enums: Vec<(String, String)>,
+    values: Vec<(String, String)>,
+}
+
+/// Build the name annotations of all the enums in a file, nested ones included.
+fn named_enums(descriptor: &FileDescriptorProto) -> EnumAnnotations {
+    let mut annotations = EnumAnnotations::default();
+    collect_named_enums(
+        descriptor.package(),
+        &descriptor.enum_type,
+        &mut annotations,
+    );
+    collect_nested_named_enums(
+        descriptor.package(),
+        &descriptor.message_type,
+        &mut annotations,
+    );
+    annotations
+}
+
+fn collect_nested_named_enums(
+    namespace: &str,
+    messages: &[DescriptorProto],
+    annotations: &mut EnumAnnotations,
+) {
+    for message in messages {
         let full_name = fully_qualified_name(namespace, message.name());
-        let derive =
-            format!("#[derive(::grpc_build_core::NamedMessage)] #[name = \"{full_name}\"]");
-        (full_name, derive)
-    })
+        collect_named_enums(&full_name, &message.enum_type, annotations);
+        collect_nested_named_enums(&full_name, &message.nested_type, annotations);
+    }
+}
+
+fn collect_named_enums(
+    namespace: &str,
+    enums: &[EnumDescriptorProto],
+    annotations: &mut EnumAnnotations,
+) {
+    for enumeration in enums {
+        let full_name = fully_qualified_name(namespace, enumeration.name());
+        // Fully qualified like the names of the messages, so that they can't match other packages
+        annotations.enums.push((
+            format!(".{full_name}"),
+            format!("#[name = \"{full_name}\"]"),
+        ));
+
+        for value in &enumeration.value {
+            let value_name = value.name();
+            annotations.values.push((
+                format!(".{full_name}.{value_name}"),
+                format!("#[name = \"{value_name}\"]"),
+            ));
+        }
+    }
 }
 
 fn fully_qualified_name(namespace: &str, name: &str) -> String {
@@ -1,42 +1,487 @@
+use std::{
+    io::Write,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
 use anyhow::Result;
-use clap::Parser;
-use grpc_build::Builder;
+use clap::{Args, Parser, ValueEnum};
+use grpc_build::{Builder, Error, Layout, ModuleStyle, Plan, Watcher};
+use serde_json::json;
+
+mod describe;
+
+/// The config file read when there is one in the working directory, unless `--config` is passed
+const CONFIG_FILE: &str = "grpc-build.toml";
 
 #[derive(Parser)]
 pub enum Command {
+    /// Generate the code of the protos into the output directory
     Build {
-        #[arg(long)]
-        in_dir: String,
+        #[command(flatten)]
+        options: Options,
 
-        #[arg(long)]
-        out_dir: String,
+        /// Overwrite the output directory if it exists
+        #[arg(short = 'f', long = "force", num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+        force: Option<bool>,
+    },
+    /// Check that the code in the output directory is up to date, without changing it
+    Check {
+        #[command(flatten)]
+        options: Options,
+    },
+    /// Generate the code, then generate it again whenever the protos change, overwriting the
+    /// output directory
+    Watch {
+        #[command(flatten)]
+        options: Options,
 
-        #[arg(short = 'c', long = "build_client")]
-        build_client: bool,
+        /// How long the protos have to stay unchanged before generating the code again
+        #[arg(long, value_name = "MILLISECONDS", default_value_t = 200)]
+        debounce: u64,
 
-        #[arg(short = 's', long = "build_server")]
-        build_server: bool,
+        /// How often the protos are checked for changes, which walks the input directories and
+        /// include paths every time
+        #[arg(long, value_name = "MILLISECONDS", default_value_t = 250)]
+        poll_interval: u64,
+    },
+    /// List the protos that would be compiled, with the module and file generated for each,
+    /// without generating anything
+    List {
+        #[command(flatten)]
+        options: Options,
 
-        #[arg(short = 'f', long = "force")]
-        force: bool,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
+    /// Print the file descriptor set the protos compile to, imports included
+    Describe {
+        #[command(flatten)]
+        options: Options,
+
+        #[arg(long, value_enum, default_value_t = DescribeFormat::Text)]
+        format: DescribeFormat,
+
+        /// Only describe the file with this name, relative to its include path. Can be repeated.
+        #[arg(long = "file", value_name = "NAME")]
+        files: Vec<String>,
+
+        /// Only describe the files of this package or its subpackages. Can be repeated.
+        #[arg(long = "package", value_name = "PACKAGE")]
+        packages: Vec<String>,
+
+        /// Only describe the files defining this message, enum or service, e.g. `pkg.Message`.
+        /// Can be repeated.
+        #[arg(long = "symbol", value_name = "NAME")]
+        symbols: Vec<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DescribeFormat {
+    Text,
+    Json,
+    /// The encoded `FileDescriptorSet`, e.g. to pipe it into other tools
+    Binary,
+}
+
+/// The options shared by the commands generating code. The flags that are passed override the
+/// options of the config file, and the boolean flags can be turned off with `=false`.
+#[derive(Args)]
+pub struct Options {
+    /// The grpc-build.toml file to read the options from [default: grpc-build.toml, if it exists]
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// The directory to find the .proto files in. Can be repeated.
+    #[arg(long)]
+    in_dir: Vec<String>,
+
+    /// The directory the code is generated into
+    #[arg(long)]
+    out_dir: Option<String>,
+
+    /// Generate the gRPC clients [default: false]
+    #[arg(short = 'c', long = "build_client", num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    build_client: Option<bool>,
+
+    /// Generate the gRPC servers [default: false]
+    #[arg(short = 's', long = "build_server", num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    build_server: Option<bool>,
+
+    /// A directory to resolve the imports from, without generating its protos. Can be repeated.
+    #[arg(long, value_name = "DIR")]
+    include_path: Vec<PathBuf>,
+
+    /// Only compile the .proto files matching this glob. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    include_only: Vec<String>,
+
+    /// Skip the .proto files matching this glob. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Follow symbolic links when finding .proto files
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    follow_links: Option<bool>,
+
+    /// Skip the build when nothing changed since the last one, tracked in a manifest file in
+    /// the output directory
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    incremental: Option<bool>,
+
+    /// How the generated modules are laid out in the output directory [default: directory]
+    #[arg(long, value_enum)]
+    layout: Option<LayoutArg>,
+
+    /// Where the modules with submodules are written to [default: non-mod-rs]
+    #[arg(long, value_enum)]
+    module_style: Option<ModuleStyleArg>,
+
+    /// The name of the file declaring the top-level modules [default: mod.rs]
+    #[arg(long, value_name = "NAME")]
+    root_file_name: Option<String>,
+
+    /// The module the protos without a package are written to
+    #[arg(long, value_name = "NAME")]
+    default_module_name: Option<String>,
+
+    /// Leave the generated files unformatted
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    no_format: Option<bool>,
+
+    /// The rustfmt binary to format the generated files with
+    #[arg(long, value_name = "PATH")]
+    rustfmt_path: Option<PathBuf>,
+
+    /// The edition rustfmt formats the generated files with [default: 2021]
+    #[arg(long, value_name = "EDITION")]
+    rustfmt_edition: Option<String>,
+
+    /// The rustfmt.toml to format the generated files with
+    #[arg(long, value_name = "PATH")]
+    rustfmt_config_path: Option<PathBuf>,
+
+    /// The prefix of the type URL of the generated messages
+    #[arg(long, value_name = "PREFIX")]
+    type_url_prefix: Option<String>,
+
+    /// Generate a `registry` module listing every generated message
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    message_registry: Option<bool>,
+
+    /// Embed the encoded file descriptor set of the protos in the root module
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    embed_file_descriptor_set: Option<bool>,
+
+    /// Embed the encoded file descriptor set of its protos in the module of every package
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    embed_package_file_descriptor_sets: Option<bool>,
+
+    /// Use the Rust definitions at RUST_PATH for the protos at PROTO_PATH. Can be repeated.
+    #[arg(long, value_name = "PROTO_PATH=RUST_PATH", value_parser = parse_pair)]
+    extern_path: Vec<(String, String)>,
+
+    /// Add an attribute to the matched messages, enums and oneofs. Can be repeated.
+    #[arg(long, value_name = "PATH=ATTRIBUTE", value_parser = parse_pair)]
+    type_attribute: Vec<(String, String)>,
+
+    /// Add an attribute to the matched fields. Can be repeated.
+    #[arg(long, value_name = "PATH=ATTRIBUTE", value_parser = parse_pair)]
+    field_attribute: Vec<(String, String)>,
+
+    /// Add an attribute to the server modules of the matched packages. Can be repeated.
+    #[arg(long, value_name = "PATH=ATTRIBUTE", value_parser = parse_pair)]
+    server_mod_attribute: Vec<(String, String)>,
+
+    /// Add an attribute to the servers of the matched services. Can be repeated.
+    #[arg(long, value_name = "PATH=ATTRIBUTE", value_parser = parse_pair)]
+    server_attribute: Vec<(String, String)>,
+
+    /// Add an attribute to the client modules of the matched packages. Can be repeated.
+    #[arg(long, value_name = "PATH=ATTRIBUTE", value_parser = parse_pair)]
+    client_mod_attribute: Vec<(String, String)>,
+
+    /// Add an attribute to the clients of the matched services. Can be repeated.
+    #[arg(long, value_name = "PATH=ATTRIBUTE", value_parser = parse_pair)]
+    client_attribute: Vec<(String, String)>,
+
+    /// Pass an argument to protoc, e.g. `--protoc-arg=--experimental_allow_proto3_optional`.
+    /// Can be repeated.
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    protoc_arg: Vec<String>,
+
+    /// Generate the well-known types instead of using the ones of `prost-types`
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true", value_name = "BOOL")]
+    compile_well_known_types: Option<bool>,
+
+    /// Generate a file including every generated module, relative to the output directory
+    #[arg(long, value_name = "PATH")]
+    include_file: Option<PathBuf>,
+
+    /// Write the file descriptor set of the protos to this path
+    #[arg(long, value_name = "PATH")]
+    file_descriptor_set_path: Option<PathBuf>,
+}
+
+/// Splits `KEY=VALUE` on the first `=`, as the values (e.g. attributes) may contain more
+fn parse_pair(pair: &str) -> Result<(String, String), String> {
+    pair.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{pair}`"))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LayoutArg {
+    /// A file per package, with a directory for its subpackages
+    Directory,
+    /// Every package inlined as nested modules into the root file
+    SingleFile,
+}
+
+impl From<LayoutArg> for Layout {
+    fn from(layout: LayoutArg) -> Self {
+        match layout {
+            LayoutArg::Directory => Layout::Directory,
+            LayoutArg::SingleFile => Layout::SingleFile,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ModuleStyleArg {
+    /// `pkg.rs`, next to the `pkg/` directory of the submodules
+    NonModRs,
+    /// `pkg/mod.rs`
+    ModRs,
+}
+
+impl From<ModuleStyleArg> for ModuleStyle {
+    fn from(style: ModuleStyleArg) -> Self {
+        match style {
+            ModuleStyleArg::NonModRs => ModuleStyle::NonModRs,
+            ModuleStyleArg::ModRs => ModuleStyle::ModRs,
+        }
+    }
+}
+
+impl Options {
+    fn builder(&self) -> Result<Builder, Error> {
+        let config = match &self.config {
+            Some(path) => Some(path.as_path()),
+            None => Some(Path::new(CONFIG_FILE)).filter(|path| path.is_file()),
+        };
+        // Unlike with the library, the clients and servers are only generated when asked for
+        let mut builder = Builder::new().build_client(false).build_server(false);
+        if let Some(path) = config {
+            builder = builder.apply_config_file(path)?;
+        }
+
+        // The flags that aren't passed leave the options of the config file as they are
+        let flags = [
+            (
+                self.build_client,
+                Builder::build_client as fn(Builder, bool) -> Builder,
+            ),
+            (self.build_server, Builder::build_server),
+            (self.follow_links, Builder::follow_links),
+            (self.message_registry, Builder::message_registry),
+            (
+                self.embed_file_descriptor_set,
+                Builder::embed_file_descriptor_set,
+            ),
+            (
+                self.embed_package_file_descriptor_sets,
+                Builder::embed_package_file_descriptor_sets,
+            ),
+            (
+                self.compile_well_known_types,
+                Builder::compile_well_known_types,
+            ),
+            (self.incremental, Builder::incremental),
+        ];
+        for (flag, set) in flags {
+            if let Some(enable) = flag {
+                builder = set(builder, enable);
+            }
+        }
+        if let Some(no_format) = self.no_format {
+            builder = builder.format(!no_format);
+        }
+
+        builder = self.in_dir.iter().fold(builder, Builder::in_dir);
+        if let Some(out_dir) = &self.out_dir {
+            builder = builder.out_dir(out_dir);
+        }
+        if let Some(layout) = self.layout {
+            builder = builder.layout(layout.into());
+        }
+        if let Some(style) = self.module_style {
+            builder = builder.module_style(style.into());
+        }
+        if let Some(name) = &self.root_file_name {
+            builder = builder.root_file_name(name);
+        }
+        if let Some(edition) = &self.rustfmt_edition {
+            builder = builder.rustfmt_edition(edition);
+        }
+
+        builder = self
+            .include_path
+            .iter()
+            .fold(builder, Builder::include_path);
+        builder = self
+            .include_only
+            .iter()
+            .fold(builder, Builder::include_only);
+        builder = self.exclude.iter().fold(builder, Builder::exclude);
+        builder = self.protoc_arg.iter().fold(builder, Builder::protoc_arg);
+
+        if let Some(name) = &self.default_module_name {
+            builder = builder.default_module_name(name);
+        }
+        if let Some(path) = &self.rustfmt_path {
+            builder = builder.rustfmt_path(path);
+        }
+        if let Some(path) = &self.rustfmt_config_path {
+            builder = builder.rustfmt_config_path(path);
+        }
+        if let Some(prefix) = &self.type_url_prefix {
+            builder = builder.type_url_prefix(prefix);
+        }
+        if let Some(path) = &self.include_file {
+            builder = builder.include_file(path);
+        }
+        if let Some(path) = &self.file_descriptor_set_path {
+            builder = builder.file_descriptor_set_path(path);
+        }
+
+        for (proto_path, rust_path) in &self.extern_path {
+            builder = builder.extern_path(proto_path, rust_path);
+        }
+        for (path, attribute) in &self.type_attribute {
+            builder = builder.type_attribute(path, attribute);
+        }
+        for (path, attribute) in &self.field_attribute {
+            builder = builder.field_attribute(path, attribute);
+        }
+        for (path, attribute) in &self.server_mod_attribute {
+            builder = builder.server_mod_attribute(path, attribute);
+        }
+        for (path, attribute) in &self.server_attribute {
+            builder = builder.server_attribute(path, attribute);
+        }
+        for (path, attribute) in &self.client_mod_attribute {
+            builder = builder.client_mod_attribute(path, attribute);
+        }
+        for (path, attribute) in &self.client_attribute {
+            builder = builder.client_attribute(path, attribute);
+        }
+        Ok(builder)
+    }
+}
+
+fn print_plan(plan: &Plan, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => {
+            for proto in &plan.protos {
+                match &proto.path {
+                    Some(path) => println!("{}", path.display()),
+                    None => println!("{}", proto.name),
+                }
+                println!("  package: {}", proto.package);
+                println!("  module:  {}", proto.module);
+                println!("  file:    {}", proto.output.display());
+            }
+            for name in &plan.excluded {
+                println!("{name} (excluded)");
+            }
+        }
+        OutputFormat::Json => {
+            let protos = plan
+                .protos
+                .iter()
+                .map(|proto| {
+                    json!({
+                        "name": proto.name,
+                        "path": proto.path,
+                        "package": proto.package,
+                        "module": proto.module,
+                        "output": proto.output,
+                    })
+                })
+                .collect::<Vec<_>>();
+            let plan = json!({ "protos": protos, "excluded": plan.excluded });
+            println!("{plan:#}");
+        }
+    }
 }
 
 fn main() -> Result<()> {
-    let command = Command::try_parse()?;
+    // Parsing errors and `--help` are printed and exit by clap itself
+    let command = Command::parse();
 
     match command {
-        Command::Build {
-            in_dir,
-            out_dir,
-            build_client,
-            build_server,
-            force,
-        } => Builder::new()
-            .build_client(build_client)
-            .build_server(build_server)
-            .force(force)
-            .out_dir(out_dir)
-            .build(in_dir),
-    }
+        Command::Build { options, force } => {
+            let mut builder = options.builder()?;
+            if let Some(force) = force {
+                builder = builder.force(force);
+            }
+            builder.build_configured()
+        }
+        Command::Check { options } => options.builder()?.check_configured(),
+        Command::Watch {
+            options,
+            debounce,
+            poll_interval,
+        } => Watcher::new(|| options.builder())
+            .debounce(Duration::from_millis(debounce))
+            .poll_interval(Duration::from_millis(poll_interval))
+            .run(|result| {
+                match result {
+                    Ok(()) => eprintln!("Generated the code, watching for changes"),
+                    Err(err) => eprintln!("Error: {err}"),
+                }
+                ControlFlow::Continue(())
+            }),
+        Command::List { options, format } => options.builder()?.plan_configured().map(|plan| {
+            print_plan(&plan, format);
+        }),
+        Command::Describe {
+            options,
+            format,
+            files,
+            packages,
+            symbols,
+        } => {
+            let encoded = options
+                .builder()?
+                .encoded_file_descriptor_set_configured()?;
+            let mut descriptors = describe::Descriptors::decode(&encoded)?;
+            let filter = describe::Filter {
+                files,
+                packages,
+                symbols,
+            };
+            filter.apply(&mut descriptors);
+
+            match format {
+                DescribeFormat::Text => print!("{}", describe::to_text(&descriptors)),
+                DescribeFormat::Json => println!("{:#}", describe::to_json(&descriptors)),
+                DescribeFormat::Binary => std::io::stdout()
+                    .lock()
+                    .write_all(&descriptors.encode_to_vec())?,
+            }
+            Ok(())
+        }
+    }?;
+
+    Ok(())
 }
@@ -0,0 +1,208 @@
+//! Contains a [`Manifest`] of everything that went into a build, which is used to skip the next
+//! build entirely when nothing has changed since.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::Error;
+
+/// The name of the manifest file, written inside the output directory.
+pub(crate) const MANIFEST_FILE: &str = ".grpc-build-manifest";
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Manifest {
+    /// Fingerprint of the builder options and the tools used
+    fingerprint: String,
+    /// The input protos, without their imports
+    inputs: BTreeSet<PathBuf>,
+    /// Content hashes of every proto that went into the build, imports included
+    sources: BTreeMap<PathBuf, String>,
+    /// Every file that was generated, relative to the output directory
+    outputs: BTreeSet<PathBuf>,
+}
+
+impl Manifest {
+    /// Creates the manifest of a build of the `inputs` protos that just completed, hashing the
+    /// sources and collecting the generated files from `out_dir`.
+    pub(crate) fn new(
+        fingerprint: String,
+        inputs: &[PathBuf],
+        sources: impl IntoIterator<Item = PathBuf>,
+        out_dir: &Path,
+    ) -> Result<Self, Error> {
+        let sources = sources
+            .into_iter()
+            .map(|path| {
+                let hash = hash_file(&path)?;
+                Ok((path, hash))
+            })
+            .collect::<Result<_, Error>>()?;
+
+        let mut outputs = BTreeSet::new();
+        for entry in WalkDir::new(out_dir) {
+            let entry = entry.map_err(Error::walk(out_dir))?;
+            if entry.file_type().is_file() && entry.file_name() != MANIFEST_FILE {
+                outputs.extend(entry.path().strip_prefix(out_dir).map(Path::to_path_buf));
+            }
+        }
+
+        Ok(Self {
+            fingerprint,
+            inputs: inputs.iter().cloned().collect(),
+            sources,
+            outputs,
+        })
+    }
+
+    /// Reads the manifest from the output directory. Returns `None` if there is none, or it
+    /// can't be understood.
+    pub(crate) fn read(out_dir: &Path) -> Option<Self> {
+        let content = fs_err::read_to_string(out_dir.join(MANIFEST_FILE)).ok()?;
+        let mut manifest = Manifest::default();
+
+        for line in content.lines().filter(|line| !line.starts_with('#')) {
+            let (kind, rest) = line.split_once(' ')?;
+            match kind {
+                "fingerprint" => manifest.fingerprint = rest.to_owned(),
+                "input" => {
+                    manifest.inputs.insert(PathBuf::from(rest));
+                }
+                "source" => {
+                    let (hash, path) = rest.split_once(' ')?;
+                    manifest
+                        .sources
+                        .insert(PathBuf::from(path), hash.to_owned());
+                }
+                "output" => {
+                    manifest.outputs.insert(PathBuf::from(rest));
+                }
+                _ => return None,
+            }
+        }
+
+        Some(manifest)
+    }
+
+    /// Writes the manifest to the output directory.
+    pub(crate) fn write(&self, out_dir: &Path) -> Result<(), Error> {
+        let mut content = String::from("# Manifest generated with `grpc_build`, do not edit\n");
+        content.push_str(&format!("fingerprint {}\n", self.fingerprint));
+        for path in &self.inputs {
+            content.push_str(&format!("input {}\n", path.display()));
+        }
+        for (path, hash) in &self.sources {
+            content.push_str(&format!("source {} {}\n", hash, path.display()));
+        }
+        for path in &self.outputs {
+            content.push_str(&format!("output {}\n", path.display()));
+        }
+
+        let path = out_dir.join(MANIFEST_FILE);
+        fs_err::write(&path, content).map_err(Error::io("write the manifest", &path))
+    }
+
+    /// The protos that went into the build
+    pub(crate) fn sources(&self) -> impl Iterator<Item = &Path> {
+        self.sources.keys().map(PathBuf::as_path)
+    }
+
+    /// Whether building again with the same `fingerprint` and exactly the same input `protos`
+    /// would produce the same output, which must still be in place in `out_dir`.
+    pub(crate) fn is_up_to_date(
+        &self,
+        fingerprint: &str,
+        protos: &[PathBuf],
+        out_dir: &Path,
+    ) -> bool {
+        self.fingerprint == fingerprint
+            && protos.len() == self.inputs.len()
+            && protos.iter().all(|proto| self.inputs.contains(proto))
+            && self
+                .sources
+                .iter()
+                .all(|(path, hash)| hash_file(path).ok().as_ref() == Some(hash))
+            && self.outputs.iter().all(|path| out_dir.join(path).is_file())
+    }
+}
+
+/// Hashes the options and the versions of the tools that went into a build. Unlike with
+/// `DefaultHasher`, the fingerprint stays the same across Rust releases.
+#[derive(Default)]
+pub(crate) struct Fingerprint(Sha256);
+
+impl Fingerprint {
+    /// Adds an option to the fingerprint. Both the name and the value are prefixed with their
+    /// length, so that consecutive options can't be mistaken for each other.
+    pub(crate) fn add(&mut self, name: &str, value: impl AsRef<[u8]>) -> &mut Self {
+        for part in [name.as_bytes(), value.as_ref()] {
+            self.0.update((part.len() as u64).to_le_bytes());
+            self.0.update(part);
+        }
+        self
+    }
+
+    pub(crate) fn finish(self) -> String {
+        to_hex(&self.0.finalize())
+    }
+}
+
+fn hash_file(path: &Path) -> Result<String, Error> {
+    let content = fs_err::read(path).map_err(Error::io("hash", path))?;
+    Ok(to_hex(&Sha256::digest(content)))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+
+    #[test]
+    fn manifest_roundtrip_and_up_to_date() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let proto = temp_dir.path().join("a.proto");
+        let out_dir = temp_dir.path().join("out");
+        std::fs::write(&proto, "syntax = \"proto3\";").unwrap();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::fs::write(out_dir.join("a.rs"), "// a.rs contents").unwrap();
+
+        let protos = vec![proto.clone()];
+        let manifest = Manifest::new("42".to_owned(), &protos, [proto.clone()], &out_dir).unwrap();
+        manifest.write(&out_dir).unwrap();
+
+        let read = Manifest::read(&out_dir).unwrap();
+        assert_eq!(read, manifest);
+        assert!(read.is_up_to_date("42", &protos, &out_dir));
+
+        // different options
+        assert!(!read.is_up_to_date("43", &protos, &out_dir));
+        // a new proto
+        assert!(!read.is_up_to_date(
+            "42",
+            &[proto.clone(), temp_dir.path().join("b.proto")],
+            &out_dir
+        ));
+        // a proto that is no longer an input, e.g. as its directory was removed from the build
+        assert!(!read.is_up_to_date("42", &[], &out_dir));
+
+        // a changed proto
+        std::fs::write(&proto, "syntax = \"proto2\";").unwrap();
+        assert!(!read.is_up_to_date("42", &protos, &out_dir));
+        std::fs::write(&proto, "syntax = \"proto3\";").unwrap();
+
+        // a missing output
+        std::fs::remove_file(out_dir.join("a.rs")).unwrap();
+        assert!(!read.is_up_to_date("42", &protos, &out_dir));
+    }
+}
@@ -0,0 +1,291 @@
+//! Merges the modules prost generates for the nested types of a message into the module of the
+//! package with the same name, if there is one. With a message `B` with nested types in package
+//! `a`, and a package `a.b`, both would otherwise be declared as `a::b`.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    ops::Range,
+    path::Path,
+};
+
+use prost_types::FileDescriptorProto;
+use syn::{spanned::Spanned, Ident, Item};
+
+use crate::{
+    ident::{sanitize_identifier, to_snake, to_upper_camel},
+    Error,
+};
+
+/// The proto files declaring the top-level items of each package, to name them when the items
+/// merged into a package clash with its own.
+#[derive(Debug, Default)]
+pub(crate) struct Origins(HashMap<(String, String), String>);
+
+impl Origins {
+    pub(crate) fn new(files: &[FileDescriptorProto]) -> Self {
+        let mut origins = HashMap::new();
+        for file in files {
+            let mut add = |ident: String| {
+                origins
+                    .entry((file.package().to_owned(), ident))
+                    .or_insert_with(|| file.name().to_owned());
+            };
+            for message in &file.message_type {
+                add(to_upper_camel(message.name()));
+                // The module of its nested types
+                add(to_snake(message.name()));
+            }
+            for enumeration in &file.enum_type {
+                add(to_upper_camel(enumeration.name()));
+            }
+            for service in &file.service {
+                let name = to_snake(service.name());
+                add(format!("{name}_client"));
+                add(format!("{name}_server"));
+            }
+        }
+        Self(origins)
+    }
+
+    /// The proto file declaring `ident` in `package`, or else the file generated for `package`.
+    fn file(&self, package: &str, ident: &Ident) -> String {
+        self.0
+            .get(&(package.to_owned(), ident.to_string()))
+            .cloned()
+            .unwrap_or_else(|| format!("{package}.rs"))
+    }
+}
+
+/// Merges the colliding modules of the flat files generated by prost in `output`, named after
+/// their package like `a.b.rs`. The `skip` file is left alone.
+pub(crate) fn merge_nested_modules(
+    output: &Path,
+    skip: &Path,
+    origins: &Origins,
+) -> Result<(), Error> {
+    let mut packages = BTreeSet::new();
+    for entry in fs_err::read_dir(output).map_err(Error::io("read", output))? {
+        let path = entry.map_err(Error::io("read", output))?.path();
+        if path.extension().is_some_and(|ext| ext == "rs") && path != skip {
+            packages.extend(
+                path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(str::to_owned),
+            );
+        }
+    }
+
+    // The parents first, as the contents they merge into their subpackages may collide as well
+    let mut queue = packages
+        .iter()
+        .map(|package| (depth(package), package.clone()))
+        .collect::<BTreeSet<_>>();
+
+    while let Some((depth, package)) = queue.pop_first() {
+        let subpackages = packages
+            .iter()
+            .filter_map(|other| other.strip_prefix(&package)?.strip_prefix('.'))
+            .filter_map(|rest| rest.split('.').next())
+            .map(|component| {
+                (
+                    sanitize_identifier(component.to_owned()),
+                    component.to_owned(),
+                )
+            })
+            .collect::<Vec<_>>();
+        if subpackages.is_empty() {
+            continue;
+        }
+
+        let path = output.join(format!("{package}.rs"));
+        let source = fs_err::read_to_string(&path).map_err(Error::io("read", &path))?;
+        let file = syn::parse_file(&source).map_err(|err| Error::GeneratedCode {
+            path: path.clone(),
+            message: err.to_string(),
+        })?;
+
+        // The code is moved as it is rather than printed from the syntax tree, to keep comments
+        let mut remaining = source.clone();
+        let colliding = file.items.iter().rev().filter_map(|item| match item {
+            Item::Mod(module) => {
+                let (brace, items) = module.content.as_ref()?;
+                let (_, component) = subpackages
+                    .iter()
+                    .find(|(ident, _)| module.ident == ident)?;
+                let contents =
+                    brace.span.open().byte_range().end..brace.span.close().byte_range().start;
+                Some((module, items, component, item.span().byte_range(), contents))
+            }
+            _ => None,
+        });
+
+        for (module, items, component, item, contents) in colliding {
+            let subpackage = format!("{package}.{component}");
+            let subpackage_path = output.join(format!("{subpackage}.rs"));
+
+            let nested = dedent(&source[contents]);
+            let content = match fs_err::read_to_string(&subpackage_path) {
+                Ok(existing) => {
+                    let existing_file =
+                        syn::parse_file(&existing).map_err(|err| Error::GeneratedCode {
+                            path: subpackage_path.clone(),
+                            message: err.to_string(),
+                        })?;
+                    let declared = existing_file
+                        .items
+                        .iter()
+                        .filter_map(item_ident)
+                        .collect::<BTreeSet<_>>();
+                    if let Some(ident) = items
+                        .iter()
+                        .filter_map(item_ident)
+                        .find(|ident| declared.contains(ident))
+                    {
+                        return Err(Error::ModuleClash {
+                            module: format!("{}::{ident}", subpackage.replace('.', "::")),
+                            file: origins.file(&package, &module.ident),
+                            other: Some(origins.file(&subpackage, ident)),
+                        });
+                    }
+                    format!("{existing}\n{nested}\n")
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => format!("{nested}\n"),
+                Err(err) => return Err(Error::io("read", &subpackage_path)(err)),
+            };
+            write(&subpackage_path, content)?;
+            remove(&mut remaining, item);
+
+            if packages.insert(subpackage.clone()) {
+                queue.insert((depth + 1, subpackage));
+            }
+        }
+
+        if remaining != source {
+            write(&path, remaining)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn depth(package: &str) -> usize {
+    package.split('.').count()
+}
+
+/// The identifier an item declares in its module, if it declares one.
+fn item_ident(item: &Item) -> Option<&Ident> {
+    match item {
+        Item::Const(item) => Some(&item.ident),
+        Item::Enum(item) => Some(&item.ident),
+        Item::Fn(item) => Some(&item.sig.ident),
+        Item::Mod(item) => Some(&item.ident),
+        Item::Static(item) => Some(&item.ident),
+        Item::Struct(item) => Some(&item.ident),
+        Item::Trait(item) => Some(&item.ident),
+        Item::Type(item) => Some(&item.ident),
+        Item::Union(item) => Some(&item.ident),
+        _ => None,
+    }
+}
+
+/// The contents of a module, without the indentation of the module.
+fn dedent(contents: &str) -> String {
+    let indent = contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    contents
+        .lines()
+        .map(|line| line.get(indent..).unwrap_or_else(|| line.trim_start()))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_owned()
+}
+
+/// Removes the item at `range` from `source`, along with the rest of its lines if it is the only
+/// thing on them.
+fn remove(source: &mut String, mut range: Range<usize>) {
+    let before = source[..range.start].trim_end_matches([' ', '\t']);
+    let after = source[range.end..].trim_start_matches([' ', '\t']);
+    if before.is_empty() || before.ends_with('\n') {
+        if let Some(rest) = after
+            .strip_prefix('\n')
+            .or_else(|| after.strip_prefix("\r\n"))
+        {
+            range = before.len()..source.len() - rest.len();
+        } else if after.is_empty() {
+            range = before.len()..source.len();
+        }
+    }
+    source.replace_range(range, "");
+}
+
+fn write(path: &Path, content: String) -> Result<(), Error> {
+    fs_err::write(path, content).map_err(Error::io("write", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_nested_modules, Origins};
+    use crate::Error;
+
+    #[test]
+    fn merges_nested_types_into_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            (
+                "a.rs",
+                "pub struct B {}\n/// Nested message and enum types in `B`.\npub mod b {\n    pub struct Nested {}\n    pub mod c {\n        pub struct Deep {}\n    }\n}\npub mod other {}\n",
+            ),
+            ("a.b.rs", "// a.b contents\npub struct Sibling {}\n"),
+            ("a.b.c.d.rs", "pub struct D {}\n"),
+            ("mod.rs", "pub mod b {}\n"),
+        ];
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+
+        merge_nested_modules(dir.path(), &dir.path().join("mod.rs"), &Origins::default()).unwrap();
+
+        let read = |name| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("a.rs"), "pub struct B {}\npub mod other {}\n");
+        assert_eq!(
+            read("a.b.rs"),
+            "// a.b contents\npub struct Sibling {}\n\npub struct Nested {}\n"
+        );
+        // The nested module of the merged module collides with `a.b.c.d` in turn
+        assert_eq!(read("a.b.c.rs"), "pub struct Deep {}\n");
+        assert_eq!(read("a.b.c.d.rs"), "pub struct D {}\n");
+        assert_eq!(read("mod.rs"), "pub mod b {}\n");
+    }
+
+    #[test]
+    fn reports_clashing_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            (
+                "a.rs",
+                "pub struct B {}\npub mod b {\n    pub struct Other {}\n}\n",
+            ),
+            ("a.b.rs", "pub struct Other {}\n"),
+        ];
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+
+        let err = merge_nested_modules(dir.path(), &dir.path().join("mod.rs"), &Origins::default())
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                Error::ModuleClash { ref module, ref file, ref other }
+                    if module == "a::b::Other" && file == "a.rs" && other.as_deref() == Some("a.b.rs")
+            ),
+            "{err}"
+        );
+    }
+}
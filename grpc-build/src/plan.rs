@@ -0,0 +1,166 @@
+//! Contains the [`Plan`] of a build, listing the module and file generated for every proto.
+
+use std::path::PathBuf;
+
+use prost_build::Module;
+use prost_types::FileDescriptorProto;
+
+use crate::{
+    ident::sanitize_identifier,
+    tree::{laid_out_module_file, Layout, LayoutOptions, ModuleStyle, Tree},
+    Inputs,
+};
+
+/// What building the protos would generate, see [`Builder::plan`](crate::Builder::plan)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Plan {
+    /// The protos code would be generated for, sorted by name
+    pub protos: Vec<PlannedProto>,
+    /// The protos that were discovered but filtered out, named relative to their include path
+    pub excluded: Vec<String>,
+}
+
+/// A proto code would be generated for
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PlannedProto {
+    /// The name of the proto, relative to its include path
+    pub name: String,
+    /// The path of the proto, if it was found in the include paths
+    pub path: Option<PathBuf>,
+    /// The protobuf package, which is empty when the proto has none
+    pub package: String,
+    /// The path of the Rust module, relative to the root module, e.g. `grpc_build::request`
+    pub module: String,
+    /// The file the module is written to, relative to the output directory
+    pub output: PathBuf,
+}
+
+pub(crate) fn plan<'a>(
+    inputs: &Inputs,
+    generated: impl Iterator<Item = &'a FileDescriptorProto>,
+    default_module_name: &str,
+    options: &LayoutOptions,
+) -> Plan {
+    let generated = generated.collect::<Vec<_>>();
+    // The modules are laid out from the files prost generates for them, named after the package
+    let flat_name = |file: &FileDescriptorProto| {
+        Module::from_protobuf_package_name(file.package()).to_file_name_or(default_module_name)
+    };
+    let tree = generated
+        .iter()
+        .map(|file| PathBuf::from(flat_name(file)))
+        .collect::<Tree>();
+
+    let mut protos = generated
+        .iter()
+        .map(|file| {
+            let module = Module::from_protobuf_package_name(file.package());
+            let flat_name = flat_name(file);
+            let parts = flat_name
+                .strip_suffix(".rs")
+                .unwrap_or(&flat_name)
+                .split('.')
+                .collect::<Vec<_>>();
+
+            let has_submodules = parts
+                .iter()
+                .try_fold(&tree, |tree, part| tree.0.get(&PathBuf::from(part)))
+                .is_some_and(|node| !node.0.is_empty());
+            let output = match options.layout {
+                Layout::SingleFile => PathBuf::from(&options.root_file_name),
+                Layout::Directory => {
+                    let path = laid_out_module_file(&module, default_module_name);
+                    if has_submodules && options.module_style == ModuleStyle::ModRs {
+                        path.with_extension("").join("mod.rs")
+                    } else {
+                        path
+                    }
+                }
+            };
+
+            PlannedProto {
+                name: file.name().to_owned(),
+                path: inputs
+                    .includes
+                    .iter()
+                    .map(|include| include.join(file.name()))
+                    .find(|path| path.is_file()),
+                package: file.package().to_owned(),
+                module: parts
+                    .iter()
+                    .map(|part| sanitize_identifier((*part).to_owned()))
+                    .collect::<Vec<_>>()
+                    .join("::"),
+                output,
+            }
+        })
+        .collect::<Vec<_>>();
+    protos.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut excluded = inputs.excluded.iter().cloned().collect::<Vec<_>>();
+    excluded.sort();
+
+    Plan { protos, excluded }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn file(name: &str, package: &str) -> FileDescriptorProto {
+        FileDescriptorProto {
+            name: Some(name.to_owned()),
+            package: Some(package.to_owned()).filter(|package| !package.is_empty()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn plan_modules_and_files() {
+        let inputs = Inputs {
+            protos: Vec::new(),
+            includes: Vec::new(),
+            names: HashSet::new(),
+            excluded: HashSet::from(["b/skipped.proto".to_owned()]),
+        };
+        let files = [
+            file("a/a.proto", "a"),
+            file("a/type/t.proto", "a.type"),
+            file("none.proto", ""),
+        ];
+        let outputs = |options: &LayoutOptions| {
+            plan(&inputs, files.iter(), "default", options)
+                .protos
+                .into_iter()
+                .map(|proto| (proto.module, proto.output))
+                .collect::<Vec<_>>()
+        };
+
+        let mut options = LayoutOptions::default();
+        assert_eq!(
+            outputs(&options),
+            [
+                ("a".to_owned(), "a.rs".into()),
+                ("a::r#type".to_owned(), "a/type.rs".into()),
+                ("default".to_owned(), "default.rs".into()),
+            ]
+        );
+
+        options.module_style = ModuleStyle::ModRs;
+        assert_eq!(outputs(&options)[0].1, PathBuf::from("a/mod.rs"));
+        assert_eq!(outputs(&options)[1].1, PathBuf::from("a/type.rs"));
+
+        options.layout = Layout::SingleFile;
+        assert!(outputs(&options)
+            .iter()
+            .all(|(_, output)| output == &PathBuf::from("mod.rs")));
+
+        let plan = plan(&inputs, files.iter(), "default", &options);
+        assert_eq!(plan.excluded, ["b/skipped.proto"]);
+        assert_eq!(plan.protos[2].package, "");
+    }
+}
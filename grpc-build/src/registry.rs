@@ -0,0 +1,154 @@
+//! Generates the registry module, which lists every generated message so that it can be handled
+//! by name with the `grpc_build_core::MessageRegistry` trait.
+
+use prost_build::Module;
+use prost_types::{DescriptorProto, FileDescriptorProto};
+
+use crate::{
+    ident::{to_snake, to_upper_camel},
+    Error,
+};
+
+/// The name of the registry module, declared in the root file
+pub(crate) const MODULE_NAME: &str = "registry";
+
+/// Generates the contents of the registry module for the messages of `files`. The messages that
+/// prost doesn't generate, because `is_extern` resolves their fully qualified name through an
+/// extern path, are left out.
+pub(crate) fn generate(
+    files: &[(Module, FileDescriptorProto)],
+    default_module_name: &str,
+    is_extern: impl Fn(&str) -> bool,
+) -> Result<String, Error> {
+    let mut messages = Vec::new();
+    for (module, descriptor) in files {
+        let root = match module.parts().next() {
+            Some(root) => root,
+            None => default_module_name,
+        };
+        if root == MODULE_NAME {
+            return Err(Error::ModuleClash {
+                module: MODULE_NAME.to_owned(),
+                file: descriptor.name().to_owned(),
+                other: None,
+            });
+        }
+
+        let mut path = String::from("super::");
+        if module.is_empty() {
+            path.push_str(default_module_name);
+        } else {
+            path.push_str(&module.parts().collect::<Vec<_>>().join("::"));
+        }
+        collect_messages(
+            descriptor.package(),
+            &path,
+            &descriptor.message_type,
+            &is_extern,
+            &mut messages,
+        );
+    }
+    messages.sort();
+
+    let mut registry = String::from("// Registry generated with `grpc_build`\n\n");
+    registry.push_str("/// Every message generated with `grpc_build`\n");
+    registry.push_str("pub static MESSAGES: &[::grpc_build_core::RegisteredMessage] = &[\n");
+    for (_, path) in &messages {
+        registry.push_str(&format!(
+            "    ::grpc_build_core::RegisteredMessage::of::<{path}>(),\n"
+        ));
+    }
+    registry.push_str("];\n\n");
+    registry.push_str(
+        "/// The generated messages, as a [`MessageRegistry`](::grpc_build_core::MessageRegistry)\n\
+         pub struct Registry;\n\n\
+         impl ::grpc_build_core::MessageRegistry for Registry {\n    \
+             fn messages(&self) -> &[::grpc_build_core::RegisteredMessage] {\n        \
+                 MESSAGES\n    \
+             }\n\
+         }\n",
+    );
+
+    Ok(registry)
+}
+
+/// Collects the full name and Rust path of `messages` and their nested messages, which prost
+/// generates in a module named after their parent.
+fn collect_messages(
+    namespace: &str,
+    module_path: &str,
+    messages: &[DescriptorProto],
+    is_extern: &impl Fn(&str) -> bool,
+    collected: &mut Vec<(String, String)>,
+) {
+    for message in messages {
+        if message.options.as_ref().is_some_and(|o| o.map_entry()) {
+            continue;
+        }
+
+        let full_name = crate::fully_qualified_name(namespace, message.name());
+        if is_extern(&format!(".{full_name}")) {
+            continue;
+        }
+        let path = format!("{module_path}::{}", to_upper_camel(message.name()));
+        collect_messages(
+            &full_name,
+            &format!("{module_path}::{}", to_snake(message.name())),
+            &message.nested_type,
+            is_extern,
+            collected,
+        );
+        collected.push((full_name, path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_build::Module;
+    use prost_types::{DescriptorProto, FileDescriptorProto};
+
+    #[test]
+    fn generate_registry() {
+        let message = |name: &str, nested_type| DescriptorProto {
+            name: Some(name.into()),
+            nested_type,
+            ..Default::default()
+        };
+        let file = |package: &str, message_type| {
+            (
+                Module::from_protobuf_package_name(package),
+                FileDescriptorProto {
+                    name: Some(format!("{package}.proto")),
+                    package: Some(package.into()),
+                    message_type,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let files = vec![
+            file(
+                "my.pkg",
+                vec![message("Outer", vec![message("inner_type", vec![])])],
+            ),
+            file("", vec![message("Loose", vec![])]),
+            file("google.protobuf", vec![message("Timestamp", vec![])]),
+            file(
+                "other.pkg",
+                vec![message("Kept", vec![]), message("Extern", vec![])],
+            ),
+        ];
+        let is_extern =
+            |path: &str| path.starts_with(".google.protobuf.") || path == ".other.pkg.Extern";
+        let registry = super::generate(&files, "default", is_extern).unwrap();
+        assert!(registry.contains("RegisteredMessage::of::<super::default::Loose>(),\n"));
+        assert!(registry.contains("RegisteredMessage::of::<super::my::pkg::Outer>(),\n"));
+        assert!(registry.contains("RegisteredMessage::of::<super::my::pkg::outer::InnerType>(),\n"));
+        assert!(registry.contains("RegisteredMessage::of::<super::other::pkg::Kept>(),\n"));
+        assert!(!registry.contains("Timestamp"));
+        assert!(!registry.contains("Extern"));
+
+        let files = vec![file("registry.v1", vec![message("Entry", vec![])])];
+        assert!(super::generate(&files, "default", |_| false).is_err());
+    }
+}
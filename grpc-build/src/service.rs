@@ -0,0 +1,102 @@
+//! Contains a [`NamedServiceGenerator`] that implements `grpc_build_core::NamedService` for the
+//! clients and servers generated by tonic.
+
+use prost_build::{Service, ServiceGenerator};
+
+pub(crate) struct NamedServiceGenerator {
+    inner: Box<dyn ServiceGenerator>,
+    build_client: bool,
+    build_server: bool,
+}
+
+impl NamedServiceGenerator {
+    pub(crate) fn new(
+        inner: Box<dyn ServiceGenerator>,
+        build_client: bool,
+        build_server: bool,
+    ) -> Self {
+        Self {
+            inner,
+            build_client,
+            build_server,
+        }
+    }
+}
+
+impl ServiceGenerator for NamedServiceGenerator {
+    fn generate(&mut self, service: Service, buf: &mut String) {
+        let body = named_service_body(&service);
+        // The same names tonic gives to the generated modules and types
+        let snake_name = naive_snake_case(&service.name);
+
+        if self.build_client {
+            buf.push_str(&format!(
+                "impl<T> ::grpc_build_core::NamedService for {snake_name}_client::{}Client<T> {{\n{body}}}\n",
+                service.name
+            ));
+        }
+        if self.build_server {
+            buf.push_str(&format!(
+                "impl<T: {snake_name}_server::{name}> ::grpc_build_core::NamedService for {snake_name}_server::{name}Server<T> {{\n{body}}}\n",
+                name = service.name
+            ));
+        }
+
+        self.inner.generate(service, buf);
+    }
+
+    fn finalize(&mut self, buf: &mut String) {
+        self.inner.finalize(buf);
+    }
+
+    fn finalize_package(&mut self, package: &str, buf: &mut String) {
+        self.inner.finalize_package(package, buf);
+    }
+}
+
+/// The associated constants of the `NamedService` impls.
+fn named_service_body(service: &Service) -> String {
+    let service_name = crate::fully_qualified_name(&service.package, &service.proto_name);
+
+    let mut body = format!("    const FULL_NAME: &'static str = \"{service_name}\";\n");
+    body.push_str("    const METHODS: &'static [::grpc_build_core::MethodDescriptor] = &[\n");
+    for method in &service.methods {
+        let kind = match (method.client_streaming, method.server_streaming) {
+            (false, false) => "Unary",
+            (true, false) => "ClientStreaming",
+            (false, true) => "ServerStreaming",
+            (true, true) => "BidiStreaming",
+        };
+        body.push_str(&format!(
+            "        ::grpc_build_core::MethodDescriptor {{
+            name: \"{name}\",
+            path: \"/{service_name}/{name}\",
+            input_type: \"{input}\",
+            output_type: \"{output}\",
+            kind: ::grpc_build_core::MethodKind::{kind},
+        }},\n",
+            name = method.proto_name,
+            input = method.input_proto_type.trim_start_matches('.'),
+            output = method.output_proto_type.trim_start_matches('.'),
+        ));
+    }
+    body.push_str("    ];\n");
+    body
+}
+
+/// The snake case conversion tonic uses for the names of the client and server modules.
+fn naive_snake_case(name: &str) -> String {
+    let mut s = String::new();
+    let mut it = name.chars().peekable();
+
+    while let Some(x) = it.next() {
+        s.push(x.to_ascii_lowercase());
+        if let Some(y) = it.peek() {
+            if y.is_uppercase() {
+                s.push('_');
+            }
+        }
+    }
+
+    s
+}
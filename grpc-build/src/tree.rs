@@ -9,8 +9,54 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::{bail, Context, Result};
 use fs_err::OpenOptions;
+use prost_build::Module;
+
+use crate::{
+    ident::{module_file_name, sanitize_identifier},
+    Error,
+};
+
+/// How the generated modules are laid out in the output directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Layout {
+    /// A file per package, in directories following the package names, declared from the root
+    /// file
+    #[default]
+    Directory,
+    /// A single root file with every package inlined as nested modules, so that it can be
+    /// included with one `include!`. The embedded descriptor sets are inlined as well.
+    SingleFile,
+}
+
+/// Where the modules with submodules are written to in the [`Layout::Directory`] layout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ModuleStyle {
+    /// `pkg.rs`, next to the `pkg/` directory of the submodules
+    #[default]
+    NonModRs,
+    /// `pkg/mod.rs`, in the directory of the submodules
+    ModRs,
+}
+
+/// How the output directory is laid out
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct LayoutOptions {
+    pub(crate) layout: Layout,
+    pub(crate) module_style: ModuleStyle,
+    /// The file declaring the top-level modules, e.g. `mod.rs` or `lib.rs`
+    pub(crate) root_file_name: String,
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self {
+            layout: Layout::default(),
+            module_style: ModuleStyle::default(),
+            root_file_name: "mod.rs".to_owned(),
+        }
+    }
+}
 
 #[derive(Default, Debug, PartialEq)]
 pub struct Tree(pub(crate) HashMap<PathBuf, Tree>);
@@ -44,32 +90,81 @@ impl Tree {
         let mut module = String::from("// Module generated with `grpc_build`\n");
         let sorted: BTreeSet<_> = self.0.keys().collect();
         for k in sorted {
-            module.push_str(&format!("pub mod {};\n", k.display()));
+            module.push_str(&format!("pub mod {};\n", module_ident(k)));
         }
 
         module.push('\n');
         module
     }
 
+    /// Generates a single module with the whole tree inlined, taking the contents of each module
+    /// from the file with its dotted name in `root`, and removing those files.
+    pub fn inline_modules(&self, root: &Path) -> Result<String, Error> {
+        let mut module = String::from("// Module generated with `grpc_build`\n");
+        self.inline_into(root, OsString::new(), &mut module)?;
+        Ok(module)
+    }
+
+    fn inline_into(
+        &self,
+        root: &Path,
+        filename: OsString,
+        module: &mut String,
+    ) -> Result<(), Error> {
+        if !filename.is_empty() {
+            let file = root.join(filename.add("rs"));
+            if file.is_file() {
+                let content = fs_err::read_to_string(&file).map_err(Error::io("read", &file))?;
+                module.push_str(&content);
+                fs_err::remove_file(&file).map_err(Error::io("remove", &file))?;
+            }
+        }
+
+        let sorted: BTreeSet<_> = self.0.keys().collect();
+        for k in sorted {
+            module.push_str(&format!("pub mod {} {{\n", module_ident(k)));
+            self.0[k].inline_into(root, filename.add(k), module)?;
+            module.push_str("}\n");
+        }
+        Ok(())
+    }
+
     /// Loop through the tree, determining where all the files should be
     /// and moving them there
-    pub fn move_paths(&self, root: &Path, filename: OsString, output: PathBuf) -> Result<()> {
+    pub fn move_paths(
+        &self,
+        root: &Path,
+        filename: OsString,
+        output: PathBuf,
+    ) -> Result<(), Error> {
+        self.move_paths_in_style(root, filename, output, ModuleStyle::NonModRs)
+    }
+
+    /// Like [`Tree::move_paths`], writing the modules with submodules in the given `style`.
+    pub fn move_paths_in_style(
+        &self,
+        root: &Path,
+        filename: OsString,
+        output: PathBuf,
+        style: ModuleStyle,
+    ) -> Result<(), Error> {
         if self.0.is_empty() {
-            fs_err::create_dir_all(root.join(&output).parent().unwrap())
-                .with_context(|| format!("could not create dir for file {}", output.display()))?;
+            let dir = root.join(&output);
+            let dir = dir.parent().unwrap();
+            fs_err::create_dir_all(dir).map_err(Error::io("create the directory", dir))?;
 
             let from = root.join(filename.add("rs"));
             let to = root.join(output.with_extension("rs"));
-            fs_err::rename(&from, &to).with_context(|| {
-                format!("could not move {} to {}", from.display(), to.display())
-            })?;
+            fs_err::rename(&from, &to).map_err(Error::io("move", &from))?;
         } else {
             for (k, tree) in &self.0 {
-                tree.move_paths(root, filename.add(k), output.join(k))?;
+                // The flat files are named after the package, the laid out ones after the module
+                let file_name = module_file_name(&module_ident(k)).to_owned();
+                tree.move_paths_in_style(root, filename.add(k), output.join(file_name), style)?;
             }
 
             if !filename.is_empty() {
-                self.create_module_file(root, filename, output)?;
+                self.create_module_file(root, filename, output, style)?;
             }
         }
         Ok(())
@@ -80,15 +175,20 @@ impl Tree {
         root: &Path,
         filename: OsString,
         output: PathBuf,
-    ) -> Result<(), anyhow::Error> {
+        style: ModuleStyle,
+    ) -> Result<(), Error> {
         let maybe_proto_file_name = root.join(filename.add("rs"));
+        let output = match style {
+            ModuleStyle::NonModRs => output,
+            ModuleStyle::ModRs => output.join("mod"),
+        };
         let dest_tmp_file_name = root.join(output.with_extension("tmp"));
         let final_dest_name = root.join(output.with_extension("rs"));
 
         // Write a temporary file with the module contents
         let modules = self.generate_module();
         fs_err::write(&dest_tmp_file_name, modules)
-            .with_context(|| format!("could not write to file {}", final_dest_name.display()))?;
+            .map_err(Error::io("write", &dest_tmp_file_name))?;
 
         // If there is a proto file in this directory, we append its contents to the already written temporary module file
         if fs_err::metadata(&maybe_proto_file_name)
@@ -99,45 +199,54 @@ impl Tree {
         }
 
         // Finally, move the temporary file to the final destination
-        fs_err::rename(&dest_tmp_file_name, &final_dest_name).with_context(|| {
-            format!(
-                "could not move {} to {}",
-                dest_tmp_file_name.display(),
-                final_dest_name.display()
-            )
-        })?;
+        fs_err::rename(&dest_tmp_file_name, &final_dest_name)
+            .map_err(Error::io("move", &dest_tmp_file_name))?;
 
         Ok(())
     }
 }
 
-fn merge_file_into(from: &PathBuf, to: &PathBuf) -> Result<(), anyhow::Error> {
+/// The path of the module file in the directory layout, relative to the output directory,
+/// before the [`ModuleStyle`] is applied.
+pub(crate) fn laid_out_module_file(module: &Module, default_module_name: &str) -> PathBuf {
+    let mut path = if module.is_empty() {
+        PathBuf::from(default_module_name)
+    } else {
+        module.parts().map(module_file_name).collect()
+    };
+    path.set_extension("rs");
+    path
+}
+
+/// The identifier the module of a package component is declared with. The components of the
+/// files generated by prost are already sanitized, but the files may come from elsewhere.
+fn module_ident(component: &Path) -> String {
+    sanitize_identifier(component.to_string_lossy().into_owned())
+}
+
+fn merge_file_into(from: &PathBuf, to: &PathBuf) -> Result<(), Error> {
     if from == to {
-        bail!("Merging files, source and destination files are the same");
+        return Err(Error::io("merge", from)(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "source and destination files are the same",
+        )));
     }
 
     let mut source = OpenOptions::new()
         .read(true)
         .open(from)
-        .with_context(|| format!("Failed to open not source file {}", to.display()))?;
+        .map_err(Error::io("open", from))?;
 
     let mut dest = OpenOptions::new()
         .create_new(false)
         .write(true)
         .append(true)
         .open(to)
-        .with_context(|| format!("Failed to open the destination file {}", from.display()))?;
-
-    std::io::copy(&mut source, &mut dest).with_context(|| {
-        format!(
-            "could not copy contents from {} to {}",
-            from.display(),
-            to.display()
-        )
-    })?;
-
-    fs_err::remove_file(from)
-        .with_context(|| format!("could not remove file {}", from.display()))?;
+        .map_err(Error::io("open", to))?;
+
+    std::io::copy(&mut source, &mut dest).map_err(Error::io("append to", to))?;
+
+    fs_err::remove_file(from).map_err(Error::io("remove", from))?;
     Ok(())
 }
 
@@ -162,7 +271,7 @@ impl OsStrExt for OsStr {
 impl Display for Tree {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (k, tree) in &self.0 {
-            write!(f, "pub mod {}", k.display())?;
+            write!(f, "pub mod {}", module_ident(k))?;
             if tree.0.is_empty() {
                 write!(f, ";")?;
             } else {
@@ -226,6 +335,39 @@ mod tests {
         assert_eq!(tree, expected);
     }
 
+    #[test]
+    fn inline_modules_nests_file_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [
+            ("grpc_build.rs", "pub struct Root;\n"),
+            ("grpc_build.client.rs", "pub struct Client;\n"),
+            ("grpc_build.request.rs", "pub struct Request;\n"),
+            ("hello.rs", "pub struct Hello;\n"),
+        ];
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+        let tree: Tree = files.iter().map(|(name, _)| PathBuf::from(name)).collect();
+
+        let expected = "// Module generated with `grpc_build`
+pub mod grpc_build {
+pub struct Root;
+pub mod client {
+pub struct Client;
+}
+pub mod request {
+pub struct Request;
+}
+}
+pub mod hello {
+pub struct Hello;
+}
+";
+
+        assert_eq!(tree.inline_modules(dir.path()).unwrap(), expected);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
     #[test]
     fn generate_module_returns_at_current_level() {
         let tree: Tree = [
@@ -0,0 +1,202 @@
+//! Contains the [`Watcher`], which rebuilds the protos whenever they change.
+
+use std::{
+    collections::BTreeMap,
+    ops::ControlFlow,
+    path::PathBuf,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{base::walk_protos, Builder, Error};
+
+/// Builds the protos of a [`Builder`], then rebuilds them whenever a `.proto` file of its input
+/// directories or include paths, or its config file, changes.
+///
+/// The changes are found by polling the modification times and lengths of the files. Every poll
+/// walks the input directories and include paths again and reads the metadata of their `.proto`
+/// files, which is cheap for the protos of a project, but adds up with large include paths like a
+/// vendored googleapis: raise the [`Watcher::poll_interval`] for those.
+///
+/// The output directory is overwritten as with [`Builder::force`], and as with every build, it
+/// is only replaced once the build succeeded.
+pub struct Watcher<F> {
+    builder: F,
+    poll_interval: Duration,
+    debounce: Duration,
+}
+
+/// What a [`Builder`] reads, which is watched for changes.
+struct Watched {
+    dirs: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+    follow_links: bool,
+}
+
+/// The modification times and lengths of the watched files, or `None` for the ones that can't be
+/// read. The length catches the changes made within the precision of the modification time.
+#[derive(PartialEq)]
+struct Snapshot(BTreeMap<PathBuf, Option<(SystemTime, u64)>>);
+
+impl<F> Watcher<F>
+where
+    F: FnMut() -> Result<Builder, Error>,
+{
+    /// Creates a watcher building with the builders created by `builder`, which is called again
+    /// for every rebuild, e.g. to read the config file again. The protos of the directories added
+    /// with [`Builder::in_dir`] are built, like with [`Builder::build_configured`].
+    pub fn new(builder: F) -> Self {
+        Self {
+            builder,
+            poll_interval: Duration::from_millis(250),
+            debounce: Duration::from_millis(200),
+        }
+    }
+
+    /// Configures how often the files are checked for changes. Every check walks the watched
+    /// directories again.
+    ///
+    /// This defaults to 250ms.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Configures how long the files have to stay unchanged before rebuilding, so that saving
+    /// several files at once only rebuilds them once.
+    ///
+    /// This defaults to 200ms.
+    pub fn debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Builds the protos, and rebuilds them on every change. `on_build` is called with the
+    /// result of every build, and the errors don't stop the watcher: it keeps watching until
+    /// `on_build` breaks.
+    ///
+    /// Only the first builder failing to be created, e.g. because of an invalid config file, is
+    /// returned as an error, as the files to watch aren't known then.
+    pub fn run(
+        mut self,
+        mut on_build: impl FnMut(Result<(), Error>) -> ControlFlow<()>,
+    ) -> Result<(), Error> {
+        let mut builder = (self.builder)()?;
+        loop {
+            let watched = Watched::new(&builder);
+            let mut snapshot = watched.snapshot();
+            if on_build(builder.force(true).build_configured()).is_break() {
+                return Ok(());
+            }
+
+            builder = loop {
+                snapshot = self.wait_for_change(&watched, snapshot);
+                match (self.builder)() {
+                    Ok(builder) => break builder,
+                    Err(err) => {
+                        if on_build(Err(err)).is_break() {
+                            return Ok(());
+                        }
+                    }
+                }
+            };
+        }
+    }
+
+    /// Waits for the watched files to change, and then to stay unchanged for the debounce
+    /// duration. Returns the files as they are then.
+    fn wait_for_change(&self, watched: &Watched, previous: Snapshot) -> Snapshot {
+        let mut current = loop {
+            thread::sleep(self.poll_interval);
+            let current = watched.snapshot();
+            if current != previous {
+                break current;
+            }
+        };
+
+        loop {
+            thread::sleep(self.debounce);
+            let next = watched.snapshot();
+            if next == current {
+                return next;
+            }
+            current = next;
+        }
+    }
+}
+
+impl Watched {
+    fn new(builder: &Builder) -> Self {
+        Self {
+            dirs: builder
+                .in_dirs
+                .iter()
+                .chain(&builder.include_paths)
+                .cloned()
+                .collect(),
+            files: builder.config_file.iter().cloned().collect(),
+            follow_links: builder.follow_links,
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let protos = self
+            .dirs
+            .iter()
+            .flat_map(|dir| walk_protos(dir, self.follow_links))
+            // An unreadable directory fails the build its change triggers
+            .filter_map(Result::ok);
+        let files = protos.chain(self.files.iter().cloned()).map(|path| {
+            let metadata = path
+                .metadata()
+                .and_then(|meta| Ok((meta.modified()?, meta.len())))
+                .ok();
+            (path, metadata)
+        });
+
+        Snapshot(files.collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_tracks_the_protos() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("grpc-build.toml");
+        fs_err::write(dir.path().join("a.proto"), "").unwrap();
+        fs_err::write(dir.path().join("b.rs"), "").unwrap();
+
+        let watched = Watched {
+            dirs: vec![dir.path().to_owned()],
+            files: vec![config.clone()],
+            follow_links: false,
+        };
+        let snapshot = watched.snapshot();
+        assert_eq!(
+            snapshot.0.keys().collect::<Vec<_>>(),
+            [&dir.path().join("a.proto"), &config]
+        );
+        // The missing config file is tracked, so that creating it is a change
+        assert_eq!(snapshot.0[&config], None);
+        assert!(snapshot == watched.snapshot());
+
+        fs_err::write(dir.path().join("c.proto"), "").unwrap();
+        assert!(snapshot != watched.snapshot());
+
+        // A change within the precision of the modification time is still seen
+        let snapshot = watched.snapshot();
+        let proto = dir.path().join("a.proto");
+        let modified = proto.metadata().unwrap().modified().unwrap();
+        fs_err::write(&proto, "syntax = \"proto3\";").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&proto)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(snapshot != watched.snapshot());
+    }
+}
@@ -0,0 +1,34 @@
+mod protos {
+    include!("collision_protos/mod.rs");
+}
+
+use grpc_build_core::{NamedEnum, NamedMessage};
+
+use protos::{
+    collisions::{
+        outer::{Inner, Kind, Other},
+        Outer,
+    },
+    no_package,
+};
+
+fn main() {
+    assert_eq!(Inner::NAME, "collisions.Outer.Inner");
+    assert_eq!(Other::NAME, "collisions.outer.Other");
+    assert_eq!(no_package::Inner::NAME, "Inner");
+    assert_eq!(no_package::inner::Nested::NAME, "Inner.Nested");
+    assert_eq!(Kind::NAME, "collisions.Outer.Kind");
+    assert_eq!(Kind::VALUES, [("KIND_UNSPECIFIED", 0)]);
+    assert_eq!(no_package::Kind::NAME, "Kind");
+    assert_eq!(
+        no_package::Kind::VALUES,
+        [("KIND_UNSPECIFIED", 0), ("KIND_OTHER", 1)]
+    );
+
+    let outer = Outer {
+        inner: Some(Inner::default()),
+        kind: Kind::Unspecified.into(),
+    };
+    let other = Other { inner: outer.inner };
+    assert!(other.inner.is_some());
+}
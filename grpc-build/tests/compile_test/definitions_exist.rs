@@ -3,12 +3,18 @@ mod protos {
     include!("protos/mod.rs");
 }
 
-use grpc_build_core::NamedMessage;
+use grpc_build_core::{
+    AnyMessage, MethodKind, MessageRegistry, NamedEnum, NamedMessage, NamedService,
+};
 use prost::Message;
 
 use protos::grpc_build::{
-    client::helloworld::greeter_client::GreeterClient, request::helloworld::HelloRequest,
-    response::helloworld::HelloReply,
+    client::helloworld::{
+        greeter_client::GreeterClient,
+        greeter_server::{Greeter, GreeterServer},
+    },
+    request::helloworld::{hello_request, HelloRequest},
+    response::helloworld::{HelloReply, Status},
 };
 
 async fn foo(
@@ -18,10 +24,87 @@ async fn foo(
     Ok(client.say_hello(req).await?.into_inner())
 }
 
+fn server_names<T: Greeter>() -> [&'static str; 2] {
+    [
+        <GreeterServer<T> as NamedService>::FULL_NAME,
+        <GreeterServer<T> as tonic::server::NamedService>::NAME,
+    ]
+}
+
 fn main() {
     assert_eq!(
         <HelloReply as NamedMessage>::NAME,
         "grpc_build.response.helloworld.HelloReply"
     );
+    assert_eq!(
+        <hello_request::Metadata as NamedMessage>::NAME,
+        "grpc_build.request.helloworld.HelloRequest.Metadata"
+    );
+    assert_eq!(
+        <hello_request::metadata::Tag as NamedMessage>::NAME,
+        "grpc_build.request.helloworld.HelloRequest.Metadata.Tag"
+    );
+    assert_eq!(
+        <Status as NamedEnum>::NAME,
+        "grpc_build.response.helloworld.Status"
+    );
+    assert_eq!(
+        <Status as NamedEnum>::VALUES,
+        &[
+            ("STATUS_UNSPECIFIED", 0),
+            ("STATUS_OK", 1),
+            ("STATUS_FAILED", 2)
+        ]
+    );
+    assert_eq!(Status::value_name(Status::Failed as i32), Some("STATUS_FAILED"));
+    assert_eq!(
+        <hello_request::metadata::Priority as NamedEnum>::NAME,
+        "grpc_build.request.helloworld.HelloRequest.Metadata.Priority"
+    );
+    assert_eq!(
+        <HelloReply as NamedMessage>::TYPE_URL,
+        "type.googleapis.com/grpc_build.response.helloworld.HelloReply"
+    );
+    let reply = HelloReply {
+        message: "hello".into(),
+    };
+    let any = reply.pack_any();
+    assert!(HelloReply::is(&any));
+    assert!(!HelloRequest::is(&any));
+    assert_eq!(HelloReply::unpack_any(&any).unwrap(), reply);
+    assert!(HelloRequest::unpack_any(&any).is_err());
+
+    let registry = protos::registry::Registry;
+    let message = registry
+        .find("grpc_build.request.helloworld.HelloRequest.Metadata.Tag")
+        .unwrap();
+    let tag = hello_request::metadata::Tag {
+        value: "tag".into(),
+    };
+    assert_eq!((message.encode)(&tag), Some(tag.encode_to_vec()));
+    let decoded = registry.decode_any(&reply.pack_any()).unwrap();
+    assert_eq!(decoded.downcast_ref(), Some(&reply));
+
+    type Client = GreeterClient<tonic::transport::Channel>;
+    assert_eq!(
+        <Client as NamedService>::FULL_NAME,
+        "grpc_build.client.helloworld.Greeter"
+    );
+    let say_hello = Client::method("SayHello").unwrap();
+    assert_eq!(say_hello.path, "/grpc_build.client.helloworld.Greeter/SayHello");
+    assert_eq!(
+        say_hello.input_type,
+        "grpc_build.request.helloworld.HelloRequest"
+    );
+    assert_eq!(
+        say_hello.output_type,
+        "grpc_build.response.helloworld.HelloReply"
+    );
+    assert_eq!(say_hello.kind, MethodKind::Unary);
+    assert_eq!(
+        Client::method("SayHelloStream").unwrap().kind,
+        MethodKind::BidiStreaming
+    );
+
     prost_types::FileDescriptorSet::decode(protos::FILE_DESCRIPTOR_SET).unwrap();
 }
@@ -0,0 +1,45 @@
+mod protos {
+    include!("embedded_protos/mod.rs");
+}
+
+use prost::Message;
+use prost_types::FileDescriptorSet;
+
+fn file_names(buf: &[u8]) -> Vec<String> {
+    FileDescriptorSet::decode(buf)
+        .unwrap()
+        .file
+        .into_iter()
+        .map(|file| file.name().to_owned())
+        .collect()
+}
+
+fn main() {
+    let mut all = file_names(protos::FILE_DESCRIPTOR_SET);
+    all.sort();
+    assert_eq!(
+        all,
+        [
+            "grpc_build/client/helloworld.proto",
+            "grpc_build/no_package/no_package.proto",
+            "grpc_build/request/helloworld.proto",
+            "grpc_build/response/helloworld.proto",
+        ]
+    );
+    assert_eq!(
+        file_names(protos::grpc_build::client::helloworld::FILE_DESCRIPTOR_SET),
+        [
+            "grpc_build/request/helloworld.proto",
+            "grpc_build/response/helloworld.proto",
+            "grpc_build/client/helloworld.proto",
+        ]
+    );
+    assert_eq!(
+        file_names(protos::grpc_build::response::helloworld::FILE_DESCRIPTOR_SET),
+        ["grpc_build/response/helloworld.proto"]
+    );
+    assert_eq!(
+        file_names(protos::some_default::FILE_DESCRIPTOR_SET),
+        ["grpc_build/no_package/no_package.proto"]
+    );
+}
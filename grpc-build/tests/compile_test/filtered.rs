@@ -0,0 +1,28 @@
+mod protos {
+    include!("protos/mod.rs");
+}
+
+mod filtered {
+    include!("filtered_protos/mod.rs");
+}
+
+use grpc_build_core::NamedMessage;
+
+use filtered::grpc_build::{
+    client::helloworld::greeter_client::GreeterClient, response::helloworld::HelloReply,
+};
+use protos::grpc_build::request::helloworld::HelloRequest;
+
+async fn foo(
+    client: &mut GreeterClient<tonic::transport::Channel>,
+    req: HelloRequest,
+) -> anyhow::Result<HelloReply> {
+    Ok(client.say_hello(req).await?.into_inner())
+}
+
+fn main() {
+    assert_eq!(
+        <HelloReply as NamedMessage>::NAME,
+        "grpc_build.response.helloworld.HelloReply"
+    );
+}
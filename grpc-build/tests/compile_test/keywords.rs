@@ -0,0 +1,18 @@
+mod protos {
+    include!("keyword_protos/mod.rs");
+}
+
+use grpc_build_core::NamedMessage;
+
+use protos::keywords::{r#type::r#async::Event, self_::v1::Wrapper};
+
+fn main() {
+    assert_eq!(Event::NAME, "keywords.type.async.Event");
+    assert_eq!(Wrapper::NAME, "keywords.self.v1.Wrapper");
+
+    let wrapper = Wrapper {
+        event: Some(Event::default()),
+    };
+    assert!(wrapper.event.is_some());
+    assert!(!protos::keywords::r#type::r#async::FILE_DESCRIPTOR_SET.is_empty());
+}
@@ -0,0 +1,23 @@
+#[path = "mod_rs_protos/lib.rs"]
+mod protos;
+
+use grpc_build_core::NamedMessage;
+use prost::Message;
+use prost_types::FileDescriptorSet;
+
+use protos::grpc_build::{request::helloworld::HelloRequest, response::helloworld::HelloReply};
+
+fn main() {
+    assert_eq!(HelloRequest::NAME, "grpc_build.request.helloworld.HelloRequest");
+    assert_eq!(HelloReply::NAME, "grpc_build.response.helloworld.HelloReply");
+
+    let file_names: Vec<_> =
+        FileDescriptorSet::decode(protos::grpc_build::response::helloworld::FILE_DESCRIPTOR_SET)
+            .unwrap()
+            .file
+            .into_iter()
+            .map(|file| file.name().to_owned())
+            .collect();
+    assert_eq!(file_names, ["grpc_build/response/helloworld.proto"]);
+    assert!(!protos::FILE_DESCRIPTOR_SET.is_empty());
+}
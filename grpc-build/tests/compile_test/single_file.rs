@@ -0,0 +1,24 @@
+mod protos {
+    include!("single_file_protos/mod.rs");
+}
+
+use grpc_build_core::{MessageRegistry, NamedMessage};
+use prost::Message;
+use prost_types::FileDescriptorSet;
+
+use protos::grpc_build::{request::helloworld::HelloRequest, response::helloworld::HelloReply};
+
+fn main() {
+    assert_eq!(HelloRequest::NAME, "grpc_build.request.helloworld.HelloRequest");
+    assert!(protos::registry::Registry.find(HelloReply::NAME).is_some());
+
+    let file_names: Vec<_> =
+        FileDescriptorSet::decode(protos::grpc_build::response::helloworld::FILE_DESCRIPTOR_SET)
+            .unwrap()
+            .file
+            .into_iter()
+            .map(|file| file.name().to_owned())
+            .collect();
+    assert_eq!(file_names, ["grpc_build/response/helloworld.proto"]);
+    assert!(!protos::FILE_DESCRIPTOR_SET.is_empty());
+}
@@ -0,0 +1,32 @@
+mod protos {
+    include!("standalone_protos/generated.rs");
+}
+
+use grpc_build_core::{MessageRegistry, NamedMessage};
+use prost::Message;
+use prost_types::FileDescriptorSet;
+
+use protos::grpc_build::response::helloworld::HelloReply;
+
+fn file_names(buf: &[u8]) -> Vec<String> {
+    FileDescriptorSet::decode(buf)
+        .unwrap()
+        .file
+        .into_iter()
+        .map(|file| file.name().to_owned())
+        .collect()
+}
+
+fn main() {
+    assert!(protos::registry::Registry.find(HelloReply::NAME).is_some());
+
+    assert_eq!(file_names(protos::FILE_DESCRIPTOR_SET).len(), 4);
+    assert_eq!(
+        file_names(protos::grpc_build::response::helloworld::FILE_DESCRIPTOR_SET),
+        ["grpc_build/response/helloworld.proto"]
+    );
+    assert_eq!(
+        file_names(protos::some_default::FILE_DESCRIPTOR_SET),
+        ["grpc_build/no_package/no_package.proto"]
+    );
+}
@@ -0,0 +1,35 @@
+mod protos {
+    include!("well_known_protos/include/protos.rs");
+}
+
+use grpc_build_core::{MessageRegistry, NamedMessage};
+use prost::Message;
+use prost_types::FileDescriptorSet;
+
+use protos::{registry::Registry, well_known::Event};
+
+fn main() {
+    let names = Registry
+        .messages()
+        .iter()
+        .map(|message| message.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, [Event::NAME]);
+
+    let event = Event {
+        name: "launch".into(),
+        at: Some(prost_types::Timestamp::default()),
+    };
+    assert_eq!(event.at.unwrap().seconds, 0);
+
+    let names = FileDescriptorSet::decode(protos::well_known::FILE_DESCRIPTOR_SET)
+        .unwrap()
+        .file
+        .into_iter()
+        .map(|file| file.name().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["google/protobuf/timestamp.proto", "well_known/events.proto"]
+    );
+}
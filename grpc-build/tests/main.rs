@@ -1,4 +1,4 @@
-use grpc_build::Builder;
+use grpc_build::{Builder, Error, Layout, ModuleStyle, Watcher};
 
 #[test]
 fn build() {
@@ -9,9 +9,916 @@ fn build() {
         .out_dir("tests/compile_test/protos")
         .file_descriptor_set_path("tests/compile_test/protos/descriptor.bin")
         .default_module_name("some_default")
+        .message_registry(true)
         .build("tests/protos/grpc_build")
         .unwrap();
 
+    Builder::new()
+        .force(true)
+        .out_dir("tests/compile_test/embedded_protos")
+        .default_module_name("some_default")
+        .embed_file_descriptor_set(true)
+        .embed_package_file_descriptor_sets(true)
+        .build("tests/protos/grpc_build")
+        .unwrap();
+
+    Builder::new()
+        .force(true)
+        .out_dir("tests/compile_test/single_file_protos")
+        .default_module_name("some_default")
+        .layout(Layout::SingleFile)
+        .message_registry(true)
+        .embed_file_descriptor_set(true)
+        .embed_package_file_descriptor_sets(true)
+        .build("tests/protos/grpc_build")
+        .unwrap();
+
+    Builder::new()
+        .force(true)
+        .out_dir("tests/compile_test/mod_rs_protos")
+        .default_module_name("some_default")
+        .module_style(ModuleStyle::ModRs)
+        .root_file_name("lib.rs")
+        .embed_file_descriptor_set(true)
+        .embed_package_file_descriptor_sets(true)
+        .build("tests/protos/grpc_build")
+        .unwrap();
+
+    Builder::new()
+        .force(true)
+        .out_dir("tests/compile_test/keyword_protos")
+        .embed_package_file_descriptor_sets(true)
+        .build("tests/protos/keywords")
+        .unwrap();
+
+    // The excluded protos imported by the generated ones are provided by the first build
+    Builder::new()
+        .force(true)
+        .out_dir("tests/compile_test/filtered_protos")
+        .include_only("**/helloworld.proto")
+        .exclude("request/*.proto")
+        .extern_path(".grpc_build.request", "crate::protos::grpc_build::request")
+        .build("tests/protos/grpc_build")
+        .unwrap();
+
+    // The single file is moved on its own, away from the directory it was generated into
+    let out_dir = tempfile::tempdir().unwrap();
+    Builder::new()
+        .out_dir(out_dir.path().join("protos"))
+        .default_module_name("some_default")
+        .layout(Layout::SingleFile)
+        .message_registry(true)
+        .embed_file_descriptor_set(true)
+        .embed_package_file_descriptor_sets(true)
+        .build("tests/protos/grpc_build")
+        .unwrap();
+    let generated = fs_err::read_dir(out_dir.path().join("protos"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(generated, ["mod.rs"]);
+    fs_err::create_dir_all("tests/compile_test/standalone_protos").unwrap();
+    fs_err::copy(
+        out_dir.path().join("protos/mod.rs"),
+        "tests/compile_test/standalone_protos/generated.rs",
+    )
+    .unwrap();
+
+    Builder::new()
+        .force(true)
+        .out_dir("tests/compile_test/collision_protos")
+        .default_module_name("no_package")
+        .build("tests/protos/collisions")
+        .unwrap();
+
+    // The well-known types are provided by prost-types, so they are left out of the registry and
+    // get no file descriptor set of their own
+    Builder::new()
+        .force(true)
+        .out_dir("tests/compile_test/well_known_protos")
+        .message_registry(true)
+        .embed_package_file_descriptor_sets(true)
+        .include_file("include/protos.rs")
+        .build("tests/protos/well_known")
+        .unwrap();
+
     let t = trybuild::TestCases::new();
     t.pass("tests/compile_test/definitions_exist.rs");
+    t.pass("tests/compile_test/embedded_file_descriptor_sets.rs");
+    t.pass("tests/compile_test/single_file.rs");
+    t.pass("tests/compile_test/mod_rs_layout.rs");
+    t.pass("tests/compile_test/keywords.rs");
+    t.pass("tests/compile_test/collisions.rs");
+    t.pass("tests/compile_test/filtered.rs");
+    t.pass("tests/compile_test/standalone.rs");
+    t.pass("tests/compile_test/well_known.rs");
+}
+
+#[test]
+fn doc_comments_are_only_kept_with_protoc() {
+    let out_dir = tempfile::tempdir().unwrap();
+    Builder::new()
+        .out_dir(out_dir.path().join("protos"))
+        .default_module_name("some_default")
+        .build("tests/protos/grpc_build")
+        .unwrap();
+
+    let generated = fs_err::read_to_string(
+        out_dir
+            .path()
+            .join("protos/grpc_build/request/helloworld.rs"),
+    )
+    .unwrap();
+    // The pure rust parser doesn't record where the definitions come from, comments included
+    assert_eq!(
+        generated.contains("The request message containing the user's name."),
+        !cfg!(feature = "pure-rust")
+    );
+}
+
+#[test]
+fn build_multiple_roots_with_include_path() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let out_dir = out_dir.path().join("protos");
+
+    Builder::new()
+        .out_dir(&out_dir)
+        .include_path("tests/include")
+        .default_module_name("some_default")
+        .build_all(["tests/protos/grpc_build", "tests/protos/imports"])
+        .unwrap();
+
+    assert!(out_dir.join("grpc_build/request/helloworld.rs").exists());
+    assert!(out_dir.join("imports.rs").exists());
+    // Only resolved as an import, so it is not generated
+    assert!(!out_dir.join("common.rs").exists());
+}
+
+#[test]
+fn build_script_reruns_on_proto_changes() {
+    let out_dir = tempfile::tempdir().unwrap();
+
+    // Cargo sets `TARGET` when running build scripts, which enables the directives
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_grpc_build"))
+        .env("TARGET", "x86_64-unknown-linux-gnu")
+        .args(["build", "--in-dir", "tests/protos/imports"])
+        .args(["--include-path", "tests/include"])
+        .arg("--out-dir")
+        .arg(out_dir.path().join("protos"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let directives = stdout
+        .lines()
+        .filter(|line| line.starts_with("cargo:rerun-if"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        directives,
+        [
+            "cargo:rerun-if-changed=tests/include",
+            // Resolved from the include path, as it is imported
+            "cargo:rerun-if-changed=tests/include/common/types.proto",
+            "cargo:rerun-if-changed=tests/protos/imports",
+            "cargo:rerun-if-changed=tests/protos/imports/imports.proto",
+            "cargo:rerun-if-env-changed=PROTOC",
+            "cargo:rerun-if-env-changed=PROTOC_INCLUDE",
+        ]
+    );
+}
+
+#[test]
+fn build_with_filters() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let out_dir = out_dir.path().join("protos");
+
+    Builder::new()
+        .out_dir(&out_dir)
+        .include_only("**/helloworld.proto")
+        .exclude("request/*.proto")
+        .build("tests/protos/grpc_build")
+        .unwrap();
+
+    assert!(out_dir.join("grpc_build/client/helloworld.rs").exists());
+    assert!(out_dir.join("grpc_build/response/helloworld.rs").exists());
+    // Imported by the client, but filtered out
+    assert!(!out_dir.join("grpc_build/request").exists());
+    assert!(!out_dir.join("_.rs").exists());
+
+    // The filtered out protos are not declared either
+    let modules = |path| {
+        let content = std::fs::read_to_string(out_dir.join(path)).unwrap();
+        content
+            .lines()
+            .filter(|line| line.starts_with("pub mod"))
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(modules("mod.rs"), ["pub mod grpc_build;"]);
+    assert_eq!(
+        modules("grpc_build.rs"),
+        ["pub mod client;", "pub mod response;"]
+    );
+}
+
+#[test]
+fn incremental_build_skips_unchanged_inputs() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let out_dir = out_dir.path().join("protos");
+    let builder = || {
+        Builder::new()
+            .out_dir(&out_dir)
+            .default_module_name("some_default")
+            .incremental(true)
+    };
+
+    builder().build("tests/protos/grpc_build").unwrap();
+    assert!(out_dir.join(".grpc-build-manifest").is_file());
+    let generated = out_dir.join("grpc_build/client/helloworld.rs");
+    let modified = std::fs::metadata(&generated).unwrap().modified().unwrap();
+
+    // Without `force`, this would fail if the output directory was not up to date
+    builder().build("tests/protos/grpc_build").unwrap();
+    assert_eq!(
+        std::fs::metadata(&generated).unwrap().modified().unwrap(),
+        modified
+    );
+
+    // A different option triggers a new build
+    builder()
+        .default_module_name("other_default")
+        .force(true)
+        .build("tests/protos/grpc_build")
+        .unwrap();
+    assert!(out_dir.join("other_default.rs").exists());
+    assert!(!out_dir.join("some_default.rs").exists());
+
+    // As do the options prost doesn't show in its `Debug` output
+    let attribute = "#[doc = \"Attribute\"]";
+    builder()
+        .default_module_name("other_default")
+        .type_attribute(".grpc_build.response", attribute)
+        .build("tests/protos/grpc_build")
+        .unwrap_err();
+    builder()
+        .default_module_name("other_default")
+        .type_attribute(".grpc_build.response", attribute)
+        .force(true)
+        .build("tests/protos/grpc_build")
+        .unwrap();
+    let response =
+        std::fs::read_to_string(out_dir.join("grpc_build/response/helloworld.rs")).unwrap();
+    assert!(response.contains("Attribute"), "{response}");
+
+    // So does dropping an input, even if its protos are still there
+    builder()
+        .default_module_name("other_default")
+        .type_attribute(".grpc_build.response", attribute)
+        .build("tests/protos/grpc_build/response")
+        .unwrap_err();
+
+    // Without the option, nothing is written to the output directory
+    let out_dir = tempfile::tempdir().unwrap();
+    let out_dir = out_dir.path().join("protos");
+    Builder::new()
+        .out_dir(&out_dir)
+        .build("tests/protos/grpc_build")
+        .unwrap();
+    assert!(!out_dir.join(".grpc-build-manifest").exists());
+    Builder::new()
+        .out_dir(&out_dir)
+        .build("tests/protos/grpc_build")
+        .unwrap_err();
+}
+
+#[test]
+fn failed_build_keeps_previous_output() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let out_dir = temp_dir.path().join("protos");
+
+    Builder::new()
+        .out_dir(&out_dir)
+        .default_module_name("some_default")
+        .build("tests/protos/grpc_build")
+        .unwrap();
+
+    Builder::new()
+        .out_dir(&out_dir)
+        .force(true)
+        .build("tests/invalid_protos/invalid")
+        .unwrap_err();
+
+    assert!(out_dir.join("grpc_build/client/helloworld.rs").exists());
+    assert!(out_dir.join("mod.rs").exists());
+    // The staging directory is cleaned up
+    assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+    // Nor is it replaced when the file descriptor set can't be written next to it
+    let marker = out_dir.join("marker");
+    std::fs::write(&marker, "").unwrap();
+    let not_a_dir = temp_dir.path().join("not_a_dir");
+    std::fs::write(&not_a_dir, "").unwrap();
+    Builder::new()
+        .out_dir(&out_dir)
+        .force(true)
+        .file_descriptor_set_path(not_a_dir.join("descriptor.bin"))
+        .build("tests/protos/grpc_build")
+        .unwrap_err();
+    assert!(marker.exists());
+
+    let descriptor = temp_dir.path().join("descriptor.bin");
+    Builder::new()
+        .out_dir(&out_dir)
+        .force(true)
+        .file_descriptor_set_path(&descriptor)
+        .build("tests/protos/grpc_build")
+        .unwrap();
+    assert!(!marker.exists());
+    assert!(descriptor.is_file());
+    assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+}
+
+#[test]
+fn check_reports_out_of_date_files() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let out_dir = out_dir.path().join("protos");
+    let builder = || {
+        Builder::new()
+            .out_dir(&out_dir)
+            .default_module_name("some_default")
+    };
+
+    let err = builder().check("tests/protos/grpc_build").unwrap_err();
+    assert!(format!("{err}").contains("added: mod.rs"));
+
+    builder().build("tests/protos/grpc_build").unwrap();
+    builder().check("tests/protos/grpc_build").unwrap();
+
+    std::fs::write(out_dir.join("mod.rs"), "// changed").unwrap();
+    std::fs::write(out_dir.join("extra.rs"), "// extra").unwrap();
+    let err = format!(
+        "{}",
+        builder().check("tests/protos/grpc_build").unwrap_err()
+    );
+    assert!(err.contains("changed: mod.rs"), "{err}");
+    assert!(err.contains("removed: extra.rs"), "{err}");
+}
+
+#[test]
+fn build_with_module_styles() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let build = |name: &str, style| {
+        let out_dir = out_dir.path().join(name);
+        Builder::new()
+            .out_dir(&out_dir)
+            .default_module_name("some_default")
+            .module_style(style)
+            .root_file_name("lib.rs")
+            .build("tests/protos/grpc_build")
+            .unwrap();
+        out_dir
+    };
+
+    let non_mod_rs = build("non_mod_rs", ModuleStyle::NonModRs);
+    assert!(non_mod_rs.join("lib.rs").is_file());
+    assert!(non_mod_rs.join("grpc_build.rs").is_file());
+    assert!(non_mod_rs.join("grpc_build/client.rs").is_file());
+    assert!(non_mod_rs.join("grpc_build/client/helloworld.rs").is_file());
+    assert!(!non_mod_rs.join("mod.rs").exists());
+
+    let mod_rs = build("mod_rs", ModuleStyle::ModRs);
+    assert!(mod_rs.join("lib.rs").is_file());
+    assert!(mod_rs.join("grpc_build/mod.rs").is_file());
+    assert!(mod_rs.join("grpc_build/client/mod.rs").is_file());
+    assert!(mod_rs.join("grpc_build/client/helloworld.rs").is_file());
+    assert!(mod_rs.join("some_default.rs").is_file());
+    assert!(!mod_rs.join("grpc_build.rs").exists());
+    assert!(!mod_rs.join("mod.rs").exists());
+}
+
+#[test]
+fn build_errors_are_typed() {
+    let out_dir = tempfile::tempdir().unwrap();
+
+    let err = Builder::new()
+        .out_dir(out_dir.path())
+        .build("tests/protos/grpc_build")
+        .unwrap_err();
+    assert!(matches!(err, Error::OutDirExists { ref path } if path == out_dir.path()));
+
+    let empty_dir = out_dir.path().join("empty");
+    fs_err::create_dir(&empty_dir).unwrap();
+    let err = Builder::new()
+        .out_dir(out_dir.path().join("protos"))
+        .build(&empty_dir)
+        .unwrap_err();
+    assert!(matches!(err, Error::NoProtosFound { .. }), "{err}");
+
+    let err = Builder::new()
+        .out_dir(out_dir.path().join("protos"))
+        .build("tests/protos/missing")
+        .unwrap_err();
+    assert!(
+        matches!(err, Error::Io { ref path, .. } if path.ends_with("missing")),
+        "{err}"
+    );
+
+    let err = Builder::new()
+        .out_dir(out_dir.path().join("protos"))
+        .include_only("[")
+        .build("tests/protos/grpc_build")
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidGlob { .. }), "{err}");
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[test]
+fn root_file_clashing_with_a_package() {
+    let dir = tempfile::tempdir().unwrap();
+    let in_dir = dir.path().join("protos");
+    fs_err::create_dir(&in_dir).unwrap();
+    fs_err::write(
+        in_dir.join("lib.proto"),
+        "syntax = \"proto3\";\npackage lib;\nmessage Library {}\n",
+    )
+    .unwrap();
+
+    let err = Builder::new()
+        .out_dir(dir.path().join("out"))
+        .root_file_name("lib.rs")
+        .build(&in_dir)
+        .unwrap_err();
+    assert!(
+        matches!(err, Error::ModuleClash { ref module, ref file, .. } if module == "lib" && file == "protos/lib.proto"),
+        "{err}"
+    );
+
+    Builder::new()
+        .out_dir(dir.path().join("out"))
+        .build(&in_dir)
+        .unwrap();
+    assert!(dir.path().join("out/lib.rs").exists());
+}
+
+#[test]
+fn nested_types_clashing_with_a_subpackage() {
+    let dir = tempfile::tempdir().unwrap();
+    let in_dir = dir.path().join("protos");
+    fs_err::create_dir(&in_dir).unwrap();
+    fs_err::write(
+        in_dir.join("collisions.proto"),
+        "syntax = \"proto3\";\npackage collisions;\nmessage Outer {\n  message Other {}\n}\n",
+    )
+    .unwrap();
+    fs_err::write(
+        in_dir.join("outer.proto"),
+        "syntax = \"proto3\";\npackage collisions.outer;\nmessage Other {}\n",
+    )
+    .unwrap();
+
+    let err = Builder::new()
+        .out_dir(dir.path().join("out"))
+        .build(&in_dir)
+        .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            Error::ModuleClash { ref module, ref file, ref other }
+                if module == "collisions::outer::Other"
+                    && file == "protos/collisions.proto"
+                    && other.as_deref() == Some("protos/outer.proto")
+        ),
+        "{err}"
+    );
+}
+
+#[test]
+fn build_reports_diagnostics() {
+    let out_dir = tempfile::tempdir().unwrap();
+
+    let err = Builder::new()
+        .out_dir(out_dir.path().join("protos"))
+        .emit_cargo_warnings(false)
+        .build("tests/invalid_protos/invalid")
+        .unwrap_err();
+
+    let diagnostics = err.diagnostics();
+    assert_eq!(diagnostics.len(), 1, "{err}");
+    assert!(
+        diagnostics[0].path.ends_with("invalid/invalid.proto"),
+        "{err}"
+    );
+    assert_eq!(diagnostics[0].line, Some(8), "{err}");
+    assert!(err.to_string().contains(&diagnostics[0].to_string()));
+}
+
+#[test]
+fn build_with_type_url_prefix() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let out_dir = out_dir.path().join("protos");
+
+    Builder::new()
+        .out_dir(&out_dir)
+        .type_url_prefix("example.com/types")
+        .default_module_name("some_default")
+        .build("tests/protos/grpc_build")
+        .unwrap();
+
+    let generated =
+        std::fs::read_to_string(out_dir.join("grpc_build/response/helloworld.rs")).unwrap();
+    assert!(generated.contains(r#"#[type_url_prefix = "example.com/types"]"#));
+}
+
+#[test]
+fn cli_generates_the_same_code_as_the_library() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let out_dir = out_dir.path().join("protos");
+    let request = ".grpc_build.request.helloworld.HelloRequest";
+
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_grpc_build"))
+        .arg("build")
+        .args(["--in-dir", "tests/protos/grpc_build"])
+        .args(["--in-dir", "tests/protos/imports"])
+        .arg("--out-dir")
+        .arg(&out_dir)
+        .args(["-c", "-s"])
+        .args(["--include-path", "tests/include"])
+        .args(["--exclude", "no_package/*"])
+        .args(["--module-style", "mod-rs", "--root-file-name", "lib.rs"])
+        .args(["--default-module-name", "some_default"])
+        .args(["--type-url-prefix", "example.com/types"])
+        .args(["--message-registry", "--embed-package-file-descriptor-sets"])
+        .arg(format!(
+            "--type-attribute={request}=#[doc = \"From the CLI\"]"
+        ))
+        .arg(format!(
+            "--field-attribute={request}.name=#[allow(missing_docs)]"
+        ))
+        .args([
+            "--client-attribute",
+            "grpc_build.client.helloworld.Greeter=#[allow(missing_docs)]",
+        ])
+        .args([
+            "--server-mod-attribute",
+            "grpc_build.client.helloworld=#[allow(missing_docs)]",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    Builder::new()
+        .out_dir(&out_dir)
+        .include_path("tests/include")
+        .exclude("no_package/*")
+        .module_style(ModuleStyle::ModRs)
+        .root_file_name("lib.rs")
+        .default_module_name("some_default")
+        .type_url_prefix("example.com/types")
+        .message_registry(true)
+        .embed_package_file_descriptor_sets(true)
+        .type_attribute(request, "#[doc = \"From the CLI\"]")
+        .field_attribute(format!("{request}.name"), "#[allow(missing_docs)]")
+        .client_attribute(
+            "grpc_build.client.helloworld.Greeter",
+            "#[allow(missing_docs)]",
+        )
+        .server_mod_attribute("grpc_build.client.helloworld", "#[allow(missing_docs)]")
+        .check_all(["tests/protos/grpc_build", "tests/protos/imports"])
+        .unwrap();
+
+    let request =
+        std::fs::read_to_string(out_dir.join("grpc_build/request/helloworld.rs")).unwrap();
+    assert!(request.contains("From the CLI"), "{request}");
+}
+
+fn write_config(dir: &std::path::Path) -> std::path::PathBuf {
+    let in_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/protos/grpc_build");
+    let path = dir.join("grpc-build.toml");
+    let config = format!(
+        r##"
+in_dirs = ['{}']
+out_dir = "protos"
+default_module_name = "some_default"
+module_style = "mod-rs"
+root_file_name = "lib.rs"
+
+[type_attributes]
+".grpc_build.request.helloworld.HelloRequest" = ['#[doc = "From the config"]']
+"##,
+        in_dir.display()
+    );
+    std::fs::write(&path, config).unwrap();
+    path
+}
+
+#[test]
+fn build_from_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = write_config(dir.path());
+
+    Builder::from_config_file(&config)
+        .unwrap()
+        .build_configured()
+        .unwrap();
+
+    // Relative to the config file
+    let out_dir = dir.path().join("protos");
+    assert!(out_dir.join("lib.rs").exists());
+    let request =
+        std::fs::read_to_string(out_dir.join("grpc_build/request/helloworld.rs")).unwrap();
+    assert!(request.contains("From the config"), "{request}");
+
+    let err = Builder::new()
+        .out_dir(&out_dir)
+        .check_configured()
+        .unwrap_err();
+    assert!(matches!(err, Error::NoInDir), "{err}");
+}
+
+#[test]
+fn cli_reads_the_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = write_config(dir.path());
+    let content = std::fs::read_to_string(&config).unwrap();
+    std::fs::write(
+        &config,
+        format!("build_server = true\nmessage_registry = true\n{content}"),
+    )
+    .unwrap();
+
+    // The flags override the config file found in the working directory, which overrides the
+    // defaults of the binary
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_grpc_build"))
+        .current_dir(dir.path())
+        .args([
+            "build",
+            "--out-dir",
+            "cli_protos",
+            "--message-registry=false",
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    Builder::new()
+        .build_client(false)
+        .apply_config_file(&config)
+        .unwrap()
+        .message_registry(false)
+        .out_dir(dir.path().join("cli_protos"))
+        .check_configured()
+        .unwrap();
+    assert!(!dir.path().join("protos").exists());
+    let client = std::fs::read_to_string(
+        dir.path()
+            .join("cli_protos/grpc_build/client/helloworld.rs"),
+    )
+    .unwrap();
+    assert!(!client.contains("pub mod greeter_client"), "{client}");
+    assert!(client.contains("pub mod greeter_server"), "{client}");
+}
+
+#[test]
+fn watch_rebuilds_on_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let in_dir = dir.path().join("protos");
+    let out_dir = dir.path().join("out");
+    let proto = in_dir.join("watched.proto");
+    std::fs::create_dir(&in_dir).unwrap();
+    std::fs::write(
+        &proto,
+        "syntax = \"proto3\";\npackage watched;\nmessage A {}\n",
+    )
+    .unwrap();
+
+    // The watcher runs on its own thread, so that the test fails rather than hangs if a change
+    // is missed
+    let (done, finished) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let generated = || std::fs::read_to_string(out_dir.join("watched.rs")).unwrap();
+        let mut builds = 0;
+        let result = Watcher::new(|| Ok(Builder::new().in_dir(&in_dir).out_dir(&out_dir)))
+            .poll_interval(std::time::Duration::from_millis(10))
+            .debounce(std::time::Duration::from_millis(20))
+            .run(|result| {
+                builds += 1;
+                match builds {
+                    1 => {
+                        result.unwrap();
+                        assert!(generated().contains("pub struct A"));
+                        std::fs::write(
+                            &proto,
+                            "syntax = \"proto3\";\npackage watched;\nmessage B {}\n",
+                        )
+                        .unwrap();
+                    }
+                    2 => {
+                        result.unwrap();
+                        assert!(generated().contains("pub struct B"));
+                        std::fs::write(
+                            &proto,
+                            "syntax = \"proto3\";\npackage watched;\nmessage {}\n",
+                        )
+                        .unwrap();
+                    }
+                    _ => {
+                        // The error is reported, and the previous output is kept
+                        assert!(!result.unwrap_err().diagnostics().is_empty());
+                        assert!(generated().contains("pub struct B"));
+                        return std::ops::ControlFlow::Break(());
+                    }
+                }
+                std::ops::ControlFlow::Continue(())
+            });
+        done.send((result, builds)).unwrap();
+    });
+
+    let (result, builds) = finished
+        .recv_timeout(std::time::Duration::from_secs(120))
+        .expect("the watcher should have rebuilt the protos on every change");
+    result.unwrap();
+    assert_eq!(builds, 3);
+}
+
+#[test]
+fn plan_matches_the_build() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let builder = |layout, style| {
+        Builder::new()
+            .out_dir(out_dir.path().join(format!("{layout:?}_{style:?}")))
+            .default_module_name("some_default")
+            .layout(layout)
+            .module_style(style)
+    };
+
+    for (layout, style) in [
+        (Layout::Directory, ModuleStyle::NonModRs),
+        (Layout::Directory, ModuleStyle::ModRs),
+        (Layout::SingleFile, ModuleStyle::NonModRs),
+    ] {
+        let in_dirs = ["tests/protos/grpc_build", "tests/protos/keywords"];
+        let plan = builder(layout, style).plan_all(in_dirs).unwrap();
+        assert_eq!(plan.protos.len(), 6);
+        builder(layout, style).build_all(in_dirs).unwrap();
+
+        let out_dir = out_dir.path().join(format!("{layout:?}_{style:?}"));
+        for proto in &plan.protos {
+            assert!(out_dir.join(&proto.output).is_file(), "{proto:?}");
+            assert!(proto.path.as_ref().unwrap().is_file(), "{proto:?}");
+        }
+    }
+}
+
+#[test]
+fn plan_leaves_out_the_well_known_imports() {
+    let plan = Builder::new().plan("tests/protos/well_known").unwrap();
+    let outputs = plan
+        .protos
+        .iter()
+        .map(|proto| proto.output.to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(outputs, ["well_known.rs"]);
+
+    // Unless they are generated as well
+    let plan = Builder::new()
+        .compile_well_known_types(true)
+        .plan("tests/protos/well_known")
+        .unwrap();
+    assert_eq!(plan.protos.len(), 2);
+}
+
+#[test]
+fn cli_lists_the_plan_as_json() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_grpc_build"))
+        .args(["list", "--format", "json"])
+        .args([
+            "--in-dir",
+            "tests/protos/grpc_build",
+            "--exclude",
+            "no_package/*",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        plan["excluded"][0],
+        "grpc_build/no_package/no_package.proto"
+    );
+    let request = plan["protos"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|proto| proto["package"] == "grpc_build.request.helloworld")
+        .unwrap();
+    assert_eq!(request["module"], "grpc_build::request::helloworld");
+    assert_eq!(request["output"], "grpc_build/request/helloworld.rs");
+}
+
+#[test]
+fn cli_describes_the_file_descriptor_set() {
+    let describe = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_grpc_build"))
+            .args(["describe", "--in-dir", "tests/protos/grpc_build"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+
+    let file_descriptor_set = Builder::new()
+        .file_descriptor_set("tests/protos/grpc_build")
+        .unwrap();
+    let binary = describe(&["--format", "binary"]);
+    assert_eq!(
+        <prost_types::FileDescriptorSet as prost::Message>::decode(&*binary).unwrap(),
+        file_descriptor_set
+    );
+
+    let binary = describe(&["--format", "binary", "--package", "grpc_build.response"]);
+    let filtered = <prost_types::FileDescriptorSet as prost::Message>::decode(&*binary).unwrap();
+    assert_eq!(filtered.file.len(), 1);
+    assert_eq!(
+        filtered.file[0].name(),
+        "grpc_build/response/helloworld.proto"
+    );
+
+    let text = String::from_utf8(describe(&[
+        "--symbol",
+        "grpc_build.request.helloworld.HelloRequest.Metadata.Tag",
+    ]))
+    .unwrap();
+    assert!(
+        text.starts_with("file grpc_build/request/helloworld.proto\n"),
+        "{text}"
+    );
+    assert!(text.contains("  syntax proto3\n"), "{text}");
+    assert!(text.contains("    string name = 1\n"), "{text}");
+    assert!(
+        text.contains(
+            "    repeated .grpc_build.request.helloworld.HelloRequest.Metadata.Tag tags = 2\n"
+        ),
+        "{text}"
+    );
+    assert_eq!(text.matches("\nfile ").count(), 0, "{text}");
+
+    let json: serde_json::Value = serde_json::from_slice(&describe(&[
+        "--format",
+        "json",
+        "--file",
+        "grpc_build/no_package/no_package.proto",
+    ]))
+    .unwrap();
+    assert_eq!(json["file"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        json["file"][0]["name"],
+        "grpc_build/no_package/no_package.proto"
+    );
+}
+
+#[test]
+fn cli_describes_the_custom_options() {
+    let describe = |format: &str| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_grpc_build"))
+            .args(["describe", "--in-dir", "tests/protos/custom_options"])
+            .args(["--format", format, "--package", "custom_options"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+
+    let text = String::from_utf8(describe("text")).unwrap();
+    assert!(
+        text.contains("  extend .google.protobuf.MessageOptions\n    string label = 50000\n"),
+        "{text}"
+    );
+    assert!(
+        text.contains("    option (custom_options.label) = \"shown\"\n"),
+        "{text}"
+    );
+
+    let json: serde_json::Value = serde_json::from_slice(&describe("json")).unwrap();
+    assert_eq!(
+        json["file"][0]["messageType"][0]["options"]["[custom_options.label]"],
+        "shown"
+    );
+
+    // The binary output is the set as it was encoded, with the files that were filtered out
+    // left out
+    let encoded = Builder::new()
+        .encoded_file_descriptor_set("tests/protos/custom_options")
+        .unwrap();
+    let binary = describe("binary");
+    assert!(encoded.ends_with(&binary));
+    let pool = prost_reflect::DescriptorPool::decode(&*encoded).unwrap();
+    let label = pool.get_extension_by_name("custom_options.label").unwrap();
+    let options = pool
+        .get_message_by_name("custom_options.Labeled")
+        .unwrap()
+        .options();
+    assert_eq!(options.get_extension(&label).as_str(), Some("shown"));
 }